@@ -1,11 +1,23 @@
+use chrono::{self, DateTime, Local};
+
+use digest::{self, Request, Schedule, Via};
 use Message;
 
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread::sleep;
 use std::time::Duration;
 
-pub fn start(wake: Sender<Message>, rx: Receiver<Message>) {
+// Wakes up every ::SLEEP seconds, and asks the poller for the digest when
+// it's due. It's taken as sent once the poller says so, and asked for
+// again every ::DIGEST_RETRY minutes until then.
+pub fn start(
+    wake: Sender<Message>,
+    rx: Receiver<Message>,
+    poller: Sender<Message>,
+    digest: Option<(Schedule, Via)>,
+) {
     let mut slept = 0;
+    let mut asked: Option<DateTime<Local>> = None;
 
     debug!("Sending first awake message to backup thread");
     let _ = wake.send(Message::Awake);
@@ -15,10 +27,25 @@ pub fn start(wake: Sender<Message>, rx: Receiver<Message>) {
             Message::Quit => break,
             Message::Awake => {
                 info!("Awaken");
+                if let Some((ref schedule, ref via)) = digest {
+                    let now = Local::now();
+                    let retry = asked.map_or(true, |asked| {
+                        now - asked >= chrono::Duration::minutes(::DIGEST_RETRY)
+                    });
+                    if retry && request_digest(schedule, via, &poller, &wake) {
+                        asked = Some(now);
+                    }
+                }
 
                 info!("Sending sleep message from awake");
                 let _ = wake.send(Message::Sleep);
             }
+            Message::DigestSent(sent) => {
+                if let Err(e) = digest::save_sent(sent) {
+                    error!("Couldn't save when the digest was sent: {}", e);
+                }
+                asked = None;
+            }
             Message::Sleep => {
                 sleep(Duration::new(1, 0));
                 slept += 1;
@@ -38,3 +65,29 @@ pub fn start(wake: Sender<Message>, rx: Receiver<Message>) {
 
     info!("Exiting backup thread");
 }
+
+// Whether it asked for it, because it's due
+fn request_digest(
+    schedule: &Schedule,
+    via: &Via,
+    poller: &Sender<Message>,
+    reply: &Sender<Message>,
+) -> bool {
+    let now = Local::now();
+    let sent = digest::load_sent();
+    if !schedule.is_due(sent, now) {
+        return false;
+    }
+
+    info!("Asking for the digest");
+    let since = sent.unwrap_or_else(|| now - schedule.period());
+    let request = Request {
+        via: via.clone(),
+        since: since,
+        reply: reply.clone(),
+    };
+    if let Err(e) = poller.send(Message::SendDigest(request)) {
+        error!("Couldn't ask for the digest: {}", e);
+    }
+    true
+}
@@ -0,0 +1,71 @@
+use serde_json;
+
+use details::Details;
+use {parser, Result};
+
+use std::collections::HashSet;
+use std::fs::{self, DirBuilder, File, OpenOptions, Permissions};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+
+// Details of a message never change, so once fetched they're kept on disk
+// until the task goes away
+fn prefix(folder: &str) -> String {
+    format!("{}-", folder.replace('/', "_"))
+}
+
+fn path(folder: &str, uid: u64) -> Result<PathBuf> {
+    let mut path = parser::get_cache_dir()?;
+    path.push(format!("{}{}.json", prefix(folder), uid));
+    Ok(path)
+}
+
+pub fn load(folder: &str, uid: u64) -> Option<Details> {
+    let file = File::open(path(folder, uid).ok()?).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+// Only the user can read the mail kept there. Each file is written aside
+// and then renamed, so a crash can't leave half of one behind.
+pub fn store(folder: &str, details: &Details) -> Result<()> {
+    let dir = parser::get_cache_dir()?;
+    DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    // As it may come from before it was private
+    fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
+
+    let path = path(folder, details.uid)?;
+    let temp = path.with_extension("json.tmp");
+    let _ = fs::remove_file(&temp);
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp)?;
+    serde_json::to_writer(file, details)?;
+    fs::rename(&temp, &path)?;
+    Ok(())
+}
+
+pub fn prune(folder: &str, uids: &HashSet<u64>) -> Result<()> {
+    let prefix = prefix(folder);
+    for entry in fs::read_dir(parser::get_cache_dir()?)? {
+        let path = entry?.path();
+        let uid = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| {
+                if s.starts_with(&prefix) {
+                    s[prefix.len()..].parse::<u64>().ok()
+                } else {
+                    None
+                }
+            });
+        if let Some(uid) = uid {
+            if !uids.contains(&uid) {
+                debug!("Removing {:?} from the cache", path);
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
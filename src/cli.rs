@@ -0,0 +1,151 @@
+use serde_json;
+
+use control::{self, Request, Response};
+use {envelope, poller, threads, Creds, Result, Task};
+
+use std::io::{BufRead, BufReader};
+
+pub const USAGE: &'static str = "Commands:
+    list            print pending tasks
+    add TITLE       add a new task
+    complete UID    complete (delete) a task
+    refresh         make the running instance poll now
+    watch           print task changes as they happen";
+
+// Prefer a running instance, so we don't open a second IMAP connection.
+// Fall back to talking to the server directly when there's none.
+pub fn run(
+    args: &[String],
+    creds: Option<Creds>,
+    folder: &str,
+    done: &str,
+) -> Result<()> {
+    let request = parse(args)?;
+
+    let running = control::socket_path()
+        .ok()
+        .and_then(|socket| control::connect(&socket));
+    if let Some(mut stream) = running {
+        debug!("Talking to the running instance");
+        if let Request::Subscribe = request {
+            control::send(&mut stream, &request)?;
+            let reader = BufReader::new(stream);
+            for line in reader.lines() {
+                let response = serde_json::from_str(&line?)?;
+                print(response)?;
+            }
+            return Ok(());
+        }
+        return print(control::request(&mut stream, &request)?);
+    }
+
+    let creds = creds.ok_or("No running instance and no --config given")?;
+    let mut imap = poller::get_connection(&creds)?;
+    let response = match request {
+        Request::Tasks => {
+            let gmail = poller::is_gmail(&mut imap)?;
+            let tasks = poller::get_tasks(&mut imap, folder, gmail)?;
+            Response::Tasks {
+                tasks: control::sorted(&tasks),
+            }
+        }
+        Request::Add { title } => {
+            let id = envelope::message_id();
+            poller::add_task(&mut imap, &creds, folder, &title, &id)?;
+            Response::Ok
+        }
+        // With the replies in its thread, as from the window
+        Request::Complete { uid } => {
+            let tasks = poller::get_tasks(&mut imap, folder, false)?;
+            for uid in threads::with_replies(&tasks, uid) {
+                poller::complete_task(&mut imap, folder, uid, done)?;
+            }
+            Response::Ok
+        }
+        Request::Refresh | Request::Subscribe | Request::Present => {
+            return Err("No running instance".into())
+        }
+    };
+    let _ = imap.logout();
+    print(response)
+}
+
+fn parse(args: &[String]) -> Result<Request> {
+    let rest = args[1..].join(" ");
+    match (&args[0][..], rest.is_empty()) {
+        ("list", true) => Ok(Request::Tasks),
+        ("add", false) => Ok(Request::Add { title: rest }),
+        ("complete", false) => match rest.parse() {
+            Ok(uid) => Ok(Request::Complete { uid: uid }),
+            Err(_) => Err(format!("Bad task UID '{}'", rest).into()),
+        },
+        ("refresh", true) => Ok(Request::Refresh),
+        ("watch", true) => Ok(Request::Subscribe),
+        _ => Err(format!("Bad command '{}'\n{}", args.join(" "), USAGE).into()),
+    }
+}
+
+fn print(response: Response) -> Result<()> {
+    match response {
+        Response::Ok => {}
+        Response::Error { message } => return Err(message.into()),
+        Response::Tasks { tasks } => for t in tasks {
+            print_task("", &t)
+        },
+        Response::Added { task } => print_task("+ ", &task),
+        Response::Removed { task } => print_task("- ", &task),
+    }
+    Ok(())
+}
+
+fn print_task(prefix: &str, task: &Task) {
+    println!("{}{}\t{}", prefix, task.uid, task.title.trim());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_commands() {
+        match parse(&args("add Call  the bank")).unwrap() {
+            Request::Add { title } => assert_eq!(title, "Call the bank"),
+            r => panic!("{:?}", r),
+        }
+        match parse(&args("complete 42")).unwrap() {
+            Request::Complete { uid } => assert_eq!(uid, 42),
+            r => panic!("{:?}", r),
+        }
+        match parse(&args("list")).unwrap() {
+            Request::Tasks => {}
+            r => panic!("{:?}", r),
+        }
+        match parse(&args("watch")).unwrap() {
+            Request::Subscribe => {}
+            r => panic!("{:?}", r),
+        }
+        match parse(&args("refresh")).unwrap() {
+            Request::Refresh => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        for line in &["remove 42", "add", "list all", "refresh now"] {
+            let e = parse(&args(line)).unwrap_err().to_string();
+            assert!(e.starts_with("Bad command"), "{}", e);
+            assert!(e.contains(USAGE));
+        }
+        let e = parse(&args("complete")).unwrap_err().to_string();
+        assert!(e.starts_with("Bad command"), "{}", e);
+        for line in &["complete abc", "complete 4 2", "complete -1"] {
+            let e = parse(&args(line)).unwrap_err().to_string();
+            assert!(e.starts_with("Bad task UID"), "{}", e);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use serde_json;
+
+use {envelope, instance, threads};
+use {Message, Result, Task};
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Seconds a subscriber has to take an event before it's dropped
+const WRITE_TIMEOUT: u64 = 2;
+
+// Requests and responses travel as one JSON object per line, e.g.:
+//   {"cmd":"tasks"}                 -> {"type":"tasks","tasks":[...]}
+//   {"cmd":"add","title":"Foo"}     -> {"type":"ok"}
+//   {"cmd":"subscribe"}             -> {"type":"ok"}, then one
+//                                      {"type":"added"|"removed",...} per
+//                                      change until the client hangs up
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub enum Request {
+    Tasks,
+    Subscribe,
+    Add { title: String },
+    Complete { uid: u64 },
+    Refresh,
+    Present,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Response {
+    Ok,
+    Error { message: String },
+    Tasks { tasks: Vec<Task> },
+    Added { task: Task },
+    Removed { task: Task },
+}
+
+#[derive(Default)]
+struct State {
+    tasks: HashSet<Task>,
+    subscribers: Vec<UnixStream>,
+}
+
+pub fn socket_path() -> Result<PathBuf> {
+    instance::runtime_path(::SOCKET)
+}
+
+pub fn start(
+    path: &Path,
+    poller: Sender<Message>,
+    ui: Sender<Message>,
+    rx: Receiver<Message>,
+) {
+    // A leftover socket from a previous run would make bind() fail
+    let _ = fs::remove_file(path);
+    let listener = match UnixListener::bind(path) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Couldn't bind control socket {:?}: {}", path, e);
+            return;
+        }
+    };
+    info!("Listening on {:?}", path);
+
+    let state = Arc::new(Mutex::new(State::default()));
+    let accept_state = state.clone();
+    let _ = thread::Builder::new()
+        .name("control-accept".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let state = accept_state.clone();
+                        let poller = poller.clone();
+                        let ui = ui.clone();
+                        thread::spawn(move || {
+                            let res = serve(stream, &state, &poller, &ui);
+                            if let Err(e) = res {
+                                debug!("Control client went away: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => error!("Couldn't accept connection: {}", e),
+                }
+            }
+        });
+
+    while let Ok(m) = rx.recv() {
+        match m {
+            Message::Quit => break,
+            Message::Tasks(tasks) => update(&state, tasks),
+            _ => {}
+        }
+    }
+
+    let _ = fs::remove_file(path);
+    info!("Exiting control thread");
+}
+
+fn update(state: &Mutex<State>, tasks: HashSet<Task>) {
+    let (events, subscribers) = {
+        let mut state = state.lock().unwrap();
+
+        // By uid, as a task whose flags changed is still the same task
+        let old: HashSet<u64> = state.tasks.iter().map(|t| t.uid).collect();
+        let new: HashSet<u64> = tasks.iter().map(|t| t.uid).collect();
+        let mut events = Vec::new();
+        for t in state.tasks.iter().filter(|t| !new.contains(&t.uid)) {
+            events.push(Response::Removed { task: t.clone() });
+        }
+        for t in tasks.iter().filter(|t| !old.contains(&t.uid)) {
+            events.push(Response::Added { task: t.clone() });
+        }
+        state.tasks = tasks;
+        let subscribers: Vec<UnixStream> =
+            state.subscribers.drain(..).collect();
+        (events, subscribers)
+    };
+
+    // Written without the lock, so a slow subscriber doesn't hold up the
+    // requests. Subscribers that can't be written to have hung up, or
+    // stopped reading and timed out.
+    let mut alive = Vec::new();
+    for mut s in subscribers {
+        if events.iter().all(|e| write_line(&mut s, e).is_ok()) {
+            alive.push(s);
+        } else {
+            // Half a line may have gone out, so it's hung up on
+            let _ = s.shutdown(Shutdown::Both);
+        }
+    }
+    state.lock().unwrap().subscribers.extend(alive);
+}
+
+fn serve(
+    stream: UnixStream,
+    state: &Mutex<State>,
+    poller: &Sender<Message>,
+    ui: &Sender<Message>,
+) -> Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        debug!("Control request: {}", line);
+
+        let response = match serde_json::from_str(&line) {
+            Err(e) => Response::Error {
+                message: e.to_string(),
+            },
+            Ok(Request::Tasks) => Response::Tasks {
+                tasks: sorted(&state.lock().unwrap().tasks),
+            },
+            Ok(Request::Subscribe) => {
+                let s = writer.try_clone()?;
+                s.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT)))?;
+                state.lock().unwrap().subscribers.push(s);
+                Response::Ok
+            }
+            // Titles go in a header, which has to stay one
+            Ok(Request::Add { title }) => {
+                match envelope::encode_header(&title) {
+                    Ok(_) => {
+                        let id = envelope::message_id();
+                        forward(poller, Message::Add(title, id))
+                    }
+                    Err(e) => Response::Error {
+                        message: e.to_string(),
+                    },
+                }
+            }
+            // Threads are completed as a whole, as from the window
+            Ok(Request::Complete { uid }) => {
+                let uids =
+                    threads::with_replies(&state.lock().unwrap().tasks, uid);
+                let mut response = Response::Ok;
+                for uid in uids {
+                    response = forward(poller, Message::Delete(uid));
+                    if let Response::Error { .. } = response {
+                        break;
+                    }
+                }
+                response
+            }
+            Ok(Request::Refresh) => forward(poller, Message::Refresh),
+            Ok(Request::Present) => forward(ui, Message::Present),
+        };
+        write_line(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+fn forward(tx: &Sender<Message>, msg: Message) -> Response {
+    match tx.send(msg) {
+        Ok(_) => Response::Ok,
+        Err(e) => Response::Error {
+            message: e.to_string(),
+        },
+    }
+}
+
+pub fn sorted(tasks: &HashSet<Task>) -> Vec<Task> {
+    let mut tasks: Vec<Task> = tasks.iter().cloned().collect();
+    tasks.sort_by_key(|t| t.uid);
+    tasks
+}
+
+fn write_line<W: Write>(w: &mut W, response: &Response) -> Result<()> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    w.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+// Client side: None means there's no running instance to talk to
+pub fn connect(path: &Path) -> Option<UnixStream> {
+    UnixStream::connect(path).ok()
+}
+
+pub fn send(stream: &mut UnixStream, request: &Request) -> Result<()> {
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+pub fn request(
+    stream: &mut UnixStream,
+    request: &Request,
+) -> Result<Response> {
+    send(stream, request)?;
+    let mut line = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::{self, TryRecvError};
+
+    // Sends the lines to a served connection, and reads a response to each
+    fn talk(
+        lines: &[&str],
+        state: State,
+    ) -> (Vec<Response>, Receiver<Message>, Receiver<Message>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (poller_tx, poller_rx) = mpsc::channel();
+        let (ui_tx, ui_rx) = mpsc::channel();
+        let served = thread::spawn(move || {
+            let state = Mutex::new(state);
+            serve(server, &state, &poller_tx, &ui_tx).unwrap();
+        });
+
+        let mut writer = client.try_clone().unwrap();
+        let mut reader = BufReader::new(client);
+        let mut responses = Vec::new();
+        for line in lines {
+            writer.write_all(format!("{}\n", line).as_bytes()).unwrap();
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut response = String::new();
+            reader.read_line(&mut response).unwrap();
+            responses.push(serde_json::from_str(&response).unwrap());
+        }
+        writer.shutdown(Shutdown::Write).unwrap();
+        served.join().unwrap();
+        (responses, poller_rx, ui_rx)
+    }
+
+    fn error(response: &Response) -> &str {
+        match *response {
+            Response::Error { ref message } => message,
+            ref r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let lines = [
+            r#"{"cmd":"add","title":"Call the bank"}"#,
+            "",
+            r#"{"cmd": "refresh"}"#,
+            r#"{"cmd":"present"}"#,
+            r#"{"cmd":"complete","uid":7}"#,
+        ];
+        let (responses, poller, ui) = talk(&lines, State::default());
+        assert_eq!(responses.len(), 4);
+        for response in responses.iter() {
+            match *response {
+                Response::Ok => {}
+                ref r => panic!("{:?}", r),
+            }
+        }
+        match poller.try_recv() {
+            Ok(Message::Add(title, id)) => {
+                assert_eq!(title, "Call the bank");
+                assert!(id.contains('@'));
+            }
+            m => panic!("{:?}", m),
+        }
+        match poller.try_recv() {
+            Ok(Message::Refresh) => {}
+            m => panic!("{:?}", m),
+        }
+        match poller.try_recv() {
+            Ok(Message::Delete(7)) => {}
+            m => panic!("{:?}", m),
+        }
+        match ui.try_recv() {
+            Ok(Message::Present) => {}
+            m => panic!("{:?}", m),
+        }
+    }
+
+    #[test]
+    fn bad_requests() {
+        let lines = [
+            r#"{"cmd":"frobnicate"}"#,
+            r#"{"cmd":"complete"}"#,
+            r#"{"cmd":"complete","uid":"7"}"#,
+            r#"{"cmd":"add","title":"Foo"#,
+            "not json",
+            r#"{"title":"Foo"}"#,
+            r#"{"cmd":"add","title":"Two\nlines"}"#,
+            // Still served after all that
+            r#"{"cmd":"tasks"}"#,
+        ];
+        let (responses, poller, _) = talk(&lines, State::default());
+        assert!(error(&responses[0]).contains("frobnicate"));
+        assert!(error(&responses[1]).contains("uid"));
+        assert!(error(&responses[2]).contains("invalid type"));
+        assert!(error(&responses[3]).contains("EOF"));
+        assert!(error(&responses[4]).contains("expected"));
+        assert!(error(&responses[5]).contains("cmd"));
+        assert!(!error(&responses[6]).is_empty());
+        match responses[7] {
+            Response::Tasks { ref tasks } => assert!(tasks.is_empty()),
+            ref r => panic!("{:?}", r),
+        }
+        assert_eq!(poller.try_recv().err(), Some(TryRecvError::Disconnected));
+    }
+}
@@ -0,0 +1,399 @@
+use email::mimeheaders::MimeContentTransferEncoding;
+use email::{FromHeader, MimeMessage};
+use encoding::label::encoding_from_whatwg_label;
+use encoding::DecoderTrap;
+use regex::Regex;
+
+use response::Value;
+use Result;
+
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Details {
+    pub uid: u64,
+    pub from: String,
+    pub date: String,
+    pub body: String,
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attachment {
+    pub name: String,
+    pub content_type: String,
+    pub size: usize,
+}
+
+// A leaf of the message's BODYSTRUCTURE, enough to fetch it on its own
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Part {
+    // As in BODY.PEEK[1.2]
+    pub section: String,
+    pub content_type: String,
+    pub charset: Option<String>,
+    pub encoding: String,
+    // Still encoded, as the server counts it
+    pub size: usize,
+    // Only attachments have one
+    pub name: Option<String>,
+}
+
+// The details from the From and Date headers, the parts of the message and
+// the contents of the one body_part picked, if any
+pub fn parse(
+    uid: u64,
+    headers: &[u8],
+    parts: &[Part],
+    body: Option<&[u8]>,
+) -> Result<Details> {
+    // An empty body after the headers makes them a complete message
+    let mut raw = String::from_utf8_lossy(headers).into_owned();
+    raw.push_str("\r\n");
+    let msg = MimeMessage::parse(&raw)?;
+
+    let mut details = Details {
+        uid: uid,
+        from: header(&msg, "From"),
+        date: header(&msg, "Date"),
+        ..Default::default()
+    };
+    details.attachments = attachments(parts)
+        .into_iter()
+        .map(|part| Attachment {
+            name: part.name.clone().unwrap_or_default(),
+            content_type: part.content_type.clone(),
+            size: decoded_size(part),
+        })
+        .collect();
+
+    if let (Some(part), Some(body)) = (body_part(parts), body) {
+        details.body = match text(part, body) {
+            Ok(ref html) if part.content_type == "text/html" => {
+                html_to_text(html)
+            }
+            Ok(text) => text,
+            Err(e) => {
+                warn!("Couldn't decode the text of {}: {}", uid, e);
+                String::new()
+            }
+        };
+    }
+    Ok(details)
+}
+
+// The leaves of a BODYSTRUCTURE, in order. A message that isn't multipart
+// is its own part 1.
+pub fn parts(structure: &Value) -> Vec<Part> {
+    let mut parts = Vec::new();
+    leaves(structure, "", &mut parts);
+    parts
+}
+
+fn leaves(structure: &Value, section: &str, out: &mut Vec<Part>) {
+    let fields = structure.list();
+
+    // Multiparts start with their children, and go on with their subtype
+    let children = fields.iter().take_while(|f| match **f {
+        Value::List(_) => true,
+        _ => false,
+    });
+    let mut multipart = false;
+    for (i, child) in children.enumerate() {
+        multipart = true;
+        let section = if section.is_empty() {
+            (i + 1).to_string()
+        } else {
+            format!("{}.{}", section, i + 1)
+        };
+        leaves(child, &section, out);
+    }
+    if multipart {
+        return;
+    }
+
+    let field = |i: usize| {
+        fields
+            .get(i)
+            .and_then(|f| f.string())
+            .unwrap_or_default()
+            .to_lowercase()
+    };
+    let content_type = format!("{}/{}", field(0), field(1));
+    let params = pairs(fields.get(2));
+
+    // Where the disposition is depends on the fields that come before it:
+    // text has its number of lines, and attached mails their envelope,
+    // structure and number of lines too
+    let disposition = match &content_type[..] {
+        t if t.starts_with("text/") => 9,
+        "message/rfc822" => 11,
+        _ => 8,
+    };
+    let (disposition, dparams) = match fields.get(disposition) {
+        Some(d) => (
+            d.list()
+                .first()
+                .and_then(|d| d.string())
+                .unwrap_or_default(),
+            pairs(d.list().get(1)),
+        ),
+        None => (String::new(), HashMap::new()),
+    };
+    let name = dparams
+        .get("filename")
+        .or_else(|| params.get("name"))
+        .cloned();
+    let name = if disposition.eq_ignore_ascii_case("attachment") {
+        Some(name.unwrap_or_else(|| String::from("unnamed")))
+    } else if disposition.eq_ignore_ascii_case("inline") {
+        None
+    } else {
+        name
+    };
+
+    out.push(Part {
+        section: if section.is_empty() { "1" } else { section }.to_string(),
+        content_type: content_type,
+        charset: params.get("charset").cloned(),
+        encoding: field(5),
+        size: fields.get(6).and_then(|f| f.number()).unwrap_or(0) as usize,
+        name: name,
+    });
+}
+
+// A parameter list like ("charset" "utf-8" "name" "a.pdf"), with the names
+// in lower case
+fn pairs(list: Option<&Value>) -> HashMap<String, String> {
+    let items = list.map(|l| l.list()).unwrap_or(&[]);
+    items
+        .chunks(2)
+        .filter_map(|pair| match (pair.first(), pair.get(1)) {
+            (Some(name), Some(value)) => {
+                Some((name.string()?.to_lowercase(), value.string()?))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn attachments(parts: &[Part]) -> Vec<&Part> {
+    parts.iter().filter(|p| p.name.is_some()).collect()
+}
+
+// The first text/plain and text/html parts that aren't attachments are the
+// candidates for the body
+pub fn body_part(parts: &[Part]) -> Option<&Part> {
+    let text = |mime: &str| {
+        parts
+            .iter()
+            .find(|p| p.name.is_none() && p.content_type == mime)
+    };
+    text("text/plain").or_else(|| text("text/html"))
+}
+
+// Base64 takes 4 bytes for every 3, and other encodings about as many
+fn decoded_size(part: &Part) -> usize {
+    if part.encoding == "base64" {
+        part.size / 4 * 3
+    } else {
+        part.size
+    }
+}
+
+// The contents of a part fetched on its own, without the transfer encoding
+pub fn decode(part: &Part, data: &[u8]) -> Result<Vec<u8>> {
+    let encoding =
+        MimeContentTransferEncoding::from_header(part.encoding.clone())
+            .unwrap_or(MimeContentTransferEncoding::Identity);
+    match encoding {
+        MimeContentTransferEncoding::Identity => Ok(data.to_vec()),
+        _ => encoding
+            .decode(&String::from_utf8_lossy(data).into_owned())
+            .ok_or_else(|| "Can't undo the transfer encoding".into()),
+    }
+}
+
+// Text in its charset, decoded from the bytes the transfer encoding leaves,
+// as 8bit ones may be in any charset. Without a known one it's taken as
+// UTF-8.
+fn text(part: &Part, data: &[u8]) -> Result<String> {
+    let bytes = decode(part, data)?;
+    let charset = part.charset.as_ref().and_then(|c| {
+        encoding_from_whatwg_label(c).or_else(|| {
+            warn!("Unknown charset {}", c);
+            None
+        })
+    });
+    match charset {
+        Some(charset) => Ok(charset
+            .decode(&bytes, DecoderTrap::Replace)
+            .map_err(|e| e.into_owned())?),
+        None => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+    }
+}
+
+fn header(msg: &MimeMessage, name: &str) -> String {
+    msg.headers
+        .get_value::<String>(name.to_string())
+        .unwrap_or_default()
+}
+
+pub fn human_size(bytes: usize) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KiB", b as f64 / 1024.0),
+        b => format!("{:.1} MiB", b as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+// Good enough for reading a mail: drop what isn't shown, turn block
+// elements into line breaks, and drop every other tag
+pub fn html_to_text(html: &str) -> String {
+    let hidden =
+        Regex::new(r"(?is)<(head|script|style)\b.*?</(head|script|style)\s*>")
+            .unwrap();
+    let breaks =
+        Regex::new(r"(?i)<\s*(br|/p|/div|/tr|/h[1-6]|/li|/blockquote)\b[^>]*>")
+            .unwrap();
+    let items = Regex::new(r"(?i)<\s*li\b[^>]*>").unwrap();
+    let tags = Regex::new(r"(?s)<[^>]*>").unwrap();
+    let blanks = Regex::new(r"\n[ \t]*(\n[ \t]*)+").unwrap();
+
+    let text = hidden.replace_all(html, "");
+    let text = breaks.replace_all(&text, "\n");
+    let text = items.replace_all(&text, "• ");
+    let text = tags.replace_all(&text, "");
+    let text = blanks.replace_all(&text, "\n\n");
+    decode_entities(text.trim())
+}
+
+fn decode_entities(text: &str) -> String {
+    let entity = Regex::new(r"&(#[0-9]+|#x[0-9a-fA-F]+|[a-zA-Z]+);").unwrap();
+    entity
+        .replace_all(text, |caps: &::regex::Captures| {
+            let name = &caps[1];
+            let c = if name.starts_with("#x") || name.starts_with("#X") {
+                u32::from_str_radix(&name[2..], 16)
+                    .ok()
+                    .and_then(::std::char::from_u32)
+            } else if name.starts_with('#') {
+                name[1..].parse().ok().and_then(::std::char::from_u32)
+            } else {
+                match name {
+                    "nbsp" => Some(' '),
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    _ => None,
+                }
+            };
+            c.map(|c| c.to_string()).unwrap_or_else(|| caps[0].to_string())
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use response;
+
+    // What Dovecot answers for a mail with an HTML alternative and a PDF
+    const STRUCTURE: &'static [u8] = b"* 3 FETCH (UID 12 BODYSTRUCTURE \
+        (((\"text\" \"plain\" (\"charset\" \"iso-8859-1\") NIL NIL \
+        \"quoted-printable\" 14 1 NIL NIL NIL NIL)(\"text\" \"html\" \
+        (\"charset\" \"utf-8\") NIL NIL \"7bit\" 30 1 NIL NIL NIL NIL) \
+        \"alternative\" (\"boundary\" \"b2\") NIL NIL NIL)(\"application\" \
+        \"pdf\" (\"name\" {8}\r\nplan.pdf) NIL NIL \"base64\" 4000 NIL \
+        (\"attachment\" (\"filename\" \"Plan (v2).pdf\")) NIL NIL) \
+        \"mixed\" (\"boundary\" \"b1\") NIL NIL NIL))\r\n\
+        A4 OK Fetch completed.\r\n";
+
+    fn structure() -> Vec<Part> {
+        let mut fetch = response::fetches(STRUCTURE).remove(0);
+        parts(&fetch.remove("BODYSTRUCTURE").unwrap())
+    }
+
+    #[test]
+    fn parse_parts() {
+        let parts = structure();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].section, "1.1");
+        assert_eq!(parts[0].content_type, "text/plain");
+        assert_eq!(parts[0].charset, Some("iso-8859-1".to_string()));
+        assert_eq!(parts[0].encoding, "quoted-printable");
+        assert_eq!(parts[0].name, None);
+        assert_eq!(parts[1].section, "1.2");
+        assert_eq!(parts[1].content_type, "text/html");
+        assert_eq!(parts[2].section, "2");
+        assert_eq!(parts[2].content_type, "application/pdf");
+        assert_eq!(parts[2].size, 4000);
+        assert_eq!(parts[2].name, Some("Plan (v2).pdf".to_string()));
+
+        assert_eq!(body_part(&parts), Some(&parts[0]));
+        assert_eq!(attachments(&parts), vec![&parts[2]]);
+    }
+
+    #[test]
+    fn parse_single_part() {
+        let raw = b"* 1 FETCH (UID 4 BODYSTRUCTURE (\"TEXT\" \"HTML\" NIL \
+                    NIL NIL \"7BIT\" 20 2 NIL NIL NIL NIL))\r\n";
+        let mut fetch = response::fetches(raw).remove(0);
+        let parts = parts(&fetch.remove("BODYSTRUCTURE").unwrap());
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].section, "1");
+        assert_eq!(parts[0].content_type, "text/html");
+        assert_eq!(body_part(&parts), Some(&parts[0]));
+    }
+
+    #[test]
+    fn parse_details() {
+        let parts = structure();
+        let headers = b"From: Alice <alice@example.com>\r\n\
+                        Date: Mon, 19 Oct 2026 10:00:00 +0200\r\n\r\n";
+        let details =
+            parse(12, headers, &parts, Some(b"Caf=E9 au lait")).unwrap();
+        assert_eq!(details.from, "Alice <alice@example.com>");
+        assert_eq!(details.date, "Mon, 19 Oct 2026 10:00:00 +0200");
+        assert_eq!(details.body, "Café au lait");
+        assert_eq!(details.attachments.len(), 1);
+        assert_eq!(details.attachments[0].name, "Plan (v2).pdf");
+        assert_eq!(details.attachments[0].size, 3000);
+    }
+
+    #[test]
+    fn decode_parts() {
+        let parts = structure();
+        assert_eq!(decode(&parts[2], b"JVBE\r\nRi0=").unwrap(), b"%PDF-");
+        // 8bit parts are taken as they come, whatever the bytes
+        let raw = Part {
+            encoding: "8bit".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(decode(&raw, b"\xff\x00").unwrap(), b"\xff\x00");
+    }
+
+    #[test]
+    fn decode_texts() {
+        let latin1 = Part {
+            content_type: "text/plain".to_string(),
+            charset: Some("iso-8859-1".to_string()),
+            encoding: "8bit".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(text(&latin1, b"Caf\xe9 cr\xe8me").unwrap(), "Café crème");
+        let base64 = Part {
+            charset: Some("windows-1252".to_string()),
+            encoding: "base64".to_string(),
+            ..latin1.clone()
+        };
+        assert_eq!(text(&base64, b"gCA1").unwrap(), "€ 5");
+        let unknown = Part {
+            charset: None,
+            ..latin1.clone()
+        };
+        assert_eq!(text(&unknown, "Café".as_bytes()).unwrap(), "Café");
+    }
+}
@@ -0,0 +1,328 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveTime};
+use serde_json;
+
+use {due, parser, Message, Result, Task};
+
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::process;
+use std::sync::mpsc::Sender;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Every {
+    Day,
+    // On Mondays
+    Week,
+}
+
+// When the digest is sent
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub every: Every,
+    pub at: NaiveTime,
+}
+
+impl Schedule {
+    // The last time a digest was due, e.g. today at 8:00 if it's later
+    // than that, or else yesterday at 8:00
+    pub fn last(&self, now: DateTime<Local>) -> DateTime<Local> {
+        let mut day = now.date();
+        if now.time() < self.at {
+            day = day.pred();
+        }
+        if self.every == Every::Week {
+            let monday = day.weekday().num_days_from_monday();
+            day = day - Duration::days(monday as i64);
+        }
+        day.and_time(self.at).unwrap_or(now)
+    }
+
+    pub fn is_due(
+        &self,
+        sent: Option<DateTime<Local>>,
+        now: DateTime<Local>,
+    ) -> bool {
+        sent.map_or(true, |sent| sent < self.last(now))
+    }
+
+    pub fn period(&self) -> Duration {
+        match self.every {
+            Every::Day => Duration::days(1),
+            Every::Week => Duration::weeks(1),
+        }
+    }
+}
+
+// "daily" or "weekly", and a time like "08:00"
+pub fn parse_schedule(every: &str, at: &str) -> Option<Schedule> {
+    let every = match every {
+        "daily" => Every::Day,
+        "weekly" => Every::Week,
+        _ => return None,
+    };
+    let at = NaiveTime::parse_from_str(at, "%H:%M").ok()?;
+    Some(Schedule {
+        every: every,
+        at: at,
+    })
+}
+
+// Appended to a folder of the IMAP account, or sent to an address by SMTP
+#[derive(Clone, Debug)]
+pub enum Via {
+    Append(String),
+    Smtp(String),
+}
+
+// What the poller gets asked to send, covering the tasks completed since,
+// and where it tells when it's sent
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub via: Via,
+    pub since: DateTime<Local>,
+    pub reply: Sender<Message>,
+}
+
+// When the last digest was sent, kept between runs so restarting doesn't
+// send another one
+pub fn load_sent() -> Option<DateTime<Local>> {
+    let path = parser::get_digest_path().ok()?;
+    let sent: DateTime<FixedOffset> =
+        serde_json::from_reader(File::open(path).ok()?).ok()?;
+    Some(sent.with_timezone(&Local))
+}
+
+pub fn save_sent(sent: DateTime<Local>) -> Result<()> {
+    let path = parser::get_digest_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let sent = sent.with_timezone(sent.offset());
+    serde_json::to_writer(File::create(path)?, &sent)?;
+    Ok(())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Completion {
+    pub title: String,
+    pub at: DateTime<FixedOffset>,
+}
+
+// The server doesn't tell when a task was completed, so they're logged as
+// they are, for as long as a weekly digest needs them
+pub fn record(title: &str) -> Result<()> {
+    let now = Local::now();
+    let mut log = completions();
+    let oldest = now - Duration::weeks(1) - Duration::days(1);
+    log.retain(|c| c.at.with_timezone(&Local) > oldest);
+    log.push(Completion {
+        title: title.to_string(),
+        at: now.with_timezone(now.offset()),
+    });
+
+    let path = parser::get_completed_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    serde_json::to_writer(File::create(path)?, &log)?;
+    Ok(())
+}
+
+pub fn completed_since(since: DateTime<Local>) -> Vec<Completion> {
+    completions()
+        .into_iter()
+        .filter(|c| c.at >= since.with_timezone(since.offset()))
+        .collect()
+}
+
+fn completions() -> Vec<Completion> {
+    parser::get_completed_path()
+        .ok()
+        .and_then(|path| File::open(path).ok())
+        .and_then(|file| serde_json::from_reader(file).ok())
+        .unwrap_or_default()
+}
+
+// Subject and text of the digest: overdue tasks first, then the rest of
+// the pending ones by due date, and the ones completed since "since"
+pub fn compose(
+    tasks: &[Task],
+    completed: &[Completion],
+    since: DateTime<Local>,
+    now: DateTime<Local>,
+) -> (String, String) {
+    let mut pending: Vec<&Task> = tasks.iter().collect();
+    pending.sort_by(|a, b| match (a.due, b.due) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.date.cmp(&b.date),
+    });
+    let (overdue, pending): (Vec<&Task>, Vec<&Task>) =
+        pending.into_iter().partition(|t| due::is_overdue(t.due, now));
+
+    let mut subject = format!("{}: {} pending", ::NAME, tasks.len());
+    if !overdue.is_empty() {
+        subject.push_str(&format!(", {} overdue", overdue.len()));
+    }
+
+    let line = |t: &Task| match t.due {
+        Some(date) => {
+            format!("  - {} ({})\n", t.title, due::describe(date, now))
+        }
+        None => format!("  - {}\n", t.title),
+    };
+    let mut text = String::new();
+    if !overdue.is_empty() {
+        text.push_str("Overdue\n");
+        text.extend(overdue.iter().map(|t| line(t)));
+        text.push('\n');
+    }
+    text.push_str("Pending\n");
+    if pending.is_empty() && overdue.is_empty() {
+        text.push_str("  Nothing\n");
+    } else if pending.is_empty() {
+        text.push_str("  Nothing else\n");
+    }
+    text.extend(pending.iter().map(|t| line(t)));
+    text.push('\n');
+
+    text.push_str(&format!(
+        "Completed since {}\n",
+        since.format("%a %e %b, %H:%M")
+    ));
+    if completed.is_empty() {
+        text.push_str("  Nothing\n");
+    }
+    for c in completed {
+        text.push_str(&format!("  - {}\n", c.title));
+    }
+    (subject, text)
+}
+
+// The digest as an email
+pub fn message(
+    from: &str,
+    to: &str,
+    subject: &str,
+    text: &str,
+    now: DateTime<Local>,
+) -> Vec<u8> {
+    format!(
+        "From: {from}\r\nTo: {to}\r\nSubject: {subject}\r\n\
+         Date: {date}\r\nMessage-ID: <digest.{id}.{pid}@mail-todo>\r\n\
+         MIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Transfer-Encoding: 8bit\r\n\r\n{text}",
+        from = from,
+        to = to,
+        subject = subject,
+        date = now.to_rfc2822(),
+        id = now.timestamp_nanos(),
+        pid = process::id(),
+        text = text.replace('\n', "\r\n"),
+    ).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32) -> DateTime<Local> {
+        Local.ymd(2026, 1, day).and_hms(hour, 0, 0)
+    }
+
+    fn task(title: &str, due: Option<DateTime<Local>>) -> Task {
+        let date = at(1, 12);
+        Task {
+            title: title.to_string(),
+            uid: 1,
+            message_id: String::new(),
+            from_name: String::new(),
+            from_address: String::new(),
+            date: Some(date.with_timezone(date.offset())),
+            received: None,
+            flags: Vec::new(),
+            tags: Vec::new(),
+            in_reply_to: String::new(),
+            references: Vec::new(),
+            parent: None,
+            size: 0,
+            due: due.map(|d| d.with_timezone(d.offset())),
+            recurrence: None,
+            priority_header: None,
+        }
+    }
+
+    fn schedule(every: &str) -> Schedule {
+        parse_schedule(every, "08:00").unwrap()
+    }
+
+    #[test]
+    fn last_daily() {
+        // Monday 12 January 2026
+        assert_eq!(schedule("daily").last(at(12, 9)), at(12, 8));
+        assert_eq!(schedule("daily").last(at(12, 7)), at(11, 8));
+    }
+
+    #[test]
+    fn last_weekly() {
+        assert_eq!(schedule("weekly").last(at(14, 10)), at(12, 8));
+        assert_eq!(schedule("weekly").last(at(12, 9)), at(12, 8));
+        assert_eq!(schedule("weekly").last(at(12, 7)), at(5, 8));
+    }
+
+    #[test]
+    fn is_due() {
+        let daily = schedule("daily");
+        assert!(daily.is_due(None, at(12, 7)));
+        assert!(daily.is_due(Some(at(11, 8)), at(12, 9)));
+        assert!(!daily.is_due(Some(at(12, 8)), at(12, 9)));
+        assert!(!daily.is_due(Some(at(11, 9)), at(12, 7)));
+
+        let weekly = schedule("weekly");
+        assert!(!weekly.is_due(Some(at(12, 9)), at(18, 20)));
+        assert!(weekly.is_due(Some(at(12, 9)), at(19, 8)));
+    }
+
+    #[test]
+    fn parse_schedules() {
+        assert_eq!(schedule("weekly").every, Every::Week);
+        assert!(parse_schedule("monthly", "08:00").is_none());
+        assert!(parse_schedule("daily", "8am").is_none());
+    }
+
+    #[test]
+    fn compose_digest() {
+        let tasks = vec![
+            task("Renew passport", None),
+            task("Pay rent", Some(at(13, 9))),
+            task("Call the bank", Some(at(9, 9))),
+        ];
+        let completed = vec![Completion {
+            title: String::from("Backup NAS"),
+            at: at(11, 18).with_timezone(at(11, 18).offset()),
+        }];
+        let (subject, text) = compose(&tasks, &completed, at(11, 8), at(12, 9));
+        assert_eq!(subject, "Mail-todo: 3 pending, 1 overdue");
+        assert_eq!(
+            text,
+            "Overdue\n  - Call the bank (overdue since Fri  9 Jan)\n\n\
+             Pending\n  - Pay rent (due tomorrow)\n  - Renew passport\n\n\
+             Completed since Sun 11 Jan, 08:00\n  - Backup NAS\n"
+        );
+    }
+
+    #[test]
+    fn compose_empty_digest() {
+        let (subject, text) = compose(&[], &[], at(11, 8), at(12, 9));
+        assert_eq!(subject, "Mail-todo: 0 pending");
+        assert_eq!(
+            text,
+            "Pending\n  Nothing\n\nCompleted since Sun 11 Jan, 08:00\n  \
+             Nothing\n"
+        );
+    }
+}
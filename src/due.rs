@@ -0,0 +1,228 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate,
+             NaiveDateTime, NaiveTime, TimeZone};
+
+// Due dates come from an X-Due header, set from the UI, or else from a
+// marker in the subject: "Pay rent @friday", "Taxes due:2026-11-01". Dates
+// without a time are due at ::DUE_HOUR.
+
+// The first marker in a subject. Relative ones like @friday are taken from
+// "today", which should be the day the message was sent.
+pub fn from_subject(
+    subject: &str,
+    today: NaiveDate,
+) -> Option<DateTime<FixedOffset>> {
+    subject
+        .split_whitespace()
+        .filter_map(|word| {
+            if word.starts_with('@') {
+                Some(&word[1..])
+            } else if word.to_lowercase().starts_with("due:") {
+                Some(&word[4..])
+            } else {
+                None
+            }
+        })
+        .filter_map(|spec| parse(spec, today))
+        .next()
+}
+
+// The value of an X-Due header: None when it can't be parsed, Some(None)
+// when it says there's no due date
+pub fn from_header(
+    value: &str,
+    today: NaiveDate,
+) -> Option<Option<DateTime<FixedOffset>>> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("none") {
+        return Some(None);
+    }
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .or_else(|| parse(value, today))
+        .map(Some)
+}
+
+pub fn to_header(due: Option<DateTime<FixedOffset>>) -> String {
+    match due {
+        Some(due) => due.to_rfc3339(),
+        None => String::from("none"),
+    }
+}
+
+// today, tomorrow, a weekday (the next one, or today), 2026-11-01 or
+// 2026-11-01T17:00
+pub fn parse(spec: &str, today: NaiveDate) -> Option<DateTime<FixedOffset>> {
+    let spec = spec
+        .trim_right_matches(|c: char| ".,;:!?)".contains(c))
+        .to_lowercase();
+
+    if let Ok(time) = NaiveDateTime::parse_from_str(&spec, "%Y-%m-%dt%H:%M") {
+        return local(time);
+    }
+    let date = match &spec[..] {
+        "today" => today,
+        "tomorrow" => today + Duration::days(1),
+        day => match weekday(day) {
+            Some(weekday) => {
+                let from = today.weekday().num_days_from_monday();
+                today + Duration::days(((7 + weekday - from) % 7) as i64)
+            }
+            None => NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()?,
+        },
+    };
+    local(date.and_time(NaiveTime::from_hms(::DUE_HOUR, 0, 0)))
+}
+
+// Days from Monday, of a name like "fri" or "friday"
+pub fn weekday(name: &str) -> Option<u32> {
+    let days = [
+        "monday", "tuesday", "wednesday", "thursday", "friday", "saturday",
+        "sunday",
+    ];
+    days.iter()
+        .position(|d| name.len() >= 3 && d.starts_with(name))
+        .map(|i| i as u32)
+}
+
+fn local(time: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    let time = Local.from_local_datetime(&time).earliest()?;
+    Some(time.with_timezone(time.offset()))
+}
+
+pub fn is_overdue(
+    due: Option<DateTime<FixedOffset>>,
+    now: DateTime<Local>,
+) -> bool {
+    due.map_or(false, |due| due.with_timezone(&Local) < now)
+}
+
+// e.g. "due today", "due Fri 31 Oct 17:00", "overdue since Mon 27 Oct"
+pub fn describe(due: DateTime<FixedOffset>, now: DateTime<Local>) -> String {
+    let due = due.with_timezone(&Local);
+    let day = if due.date() == now.date() {
+        String::from("today")
+    } else if due.date() == now.date() + Duration::days(1) {
+        String::from("tomorrow")
+    } else {
+        due.format("%a %e %b").to_string()
+    };
+    let time = if due.time() == NaiveTime::from_hms(::DUE_HOUR, 0, 0) {
+        day
+    } else {
+        format!("{} {}", day, due.format("%H:%M"))
+    };
+
+    if due < now {
+        format!("overdue since {}", time)
+    } else {
+        format!("due {}", time)
+    }
+}
+
+// Search filters: due:today, due:tomorrow, due:week (the next 7 days, and
+// overdue tasks), due:overdue, due:none, due:any or a date like
+// due:2026-11-01
+pub fn matches(
+    filter: &str,
+    due: Option<DateTime<FixedOffset>>,
+    now: DateTime<Local>,
+) -> bool {
+    let day = due.map(|d| d.with_timezone(&Local).date().naive_local());
+    let today = now.date().naive_local();
+    match filter {
+        "none" => due.is_none(),
+        "any" => due.is_some(),
+        "overdue" => is_overdue(due, now),
+        "today" => day == Some(today),
+        "tomorrow" => day == Some(today + Duration::days(1)),
+        "week" => day.map_or(false, |d| d < today + Duration::days(7)),
+        date => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) => day == Some(date),
+            Err(_) => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Wednesday 21 October 2026
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd(2026, 10, 21)
+    }
+
+    fn at(month: u32, day: u32, hour: u32) -> DateTime<FixedOffset> {
+        let time = Local.ymd(2026, month, day).and_hms(hour, 0, 0);
+        time.with_timezone(time.offset())
+    }
+
+    #[test]
+    fn parse_days() {
+        assert_eq!(parse("today", today()), Some(at(10, 21, ::DUE_HOUR)));
+        assert_eq!(parse("Tomorrow", today()), Some(at(10, 22, ::DUE_HOUR)));
+        assert_eq!(parse("2026-11-01", today()), Some(at(11, 1, ::DUE_HOUR)));
+        assert_eq!(parse("2026-11-01T17:00", today()), Some(at(11, 1, 17)));
+        assert_eq!(parse("2026-11-01,", today()), Some(at(11, 1, ::DUE_HOUR)));
+        assert_eq!(parse("2026-13-01", today()), None);
+        assert_eq!(parse("2026-11-01T25:00", today()), None);
+        assert_eq!(parse("someday", today()), None);
+        assert_eq!(parse("", today()), None);
+    }
+
+    #[test]
+    fn parse_weekdays() {
+        assert_eq!(parse("fri", today()), Some(at(10, 23, ::DUE_HOUR)));
+        assert_eq!(parse("Friday.", today()), Some(at(10, 23, ::DUE_HOUR)));
+        // The same day is today, and earlier ones next week
+        assert_eq!(parse("wednesday", today()), Some(at(10, 21, ::DUE_HOUR)));
+        assert_eq!(parse("mon", today()), Some(at(10, 26, ::DUE_HOUR)));
+        assert_eq!(parse("mo", today()), None);
+        assert_eq!(weekday("sunday"), Some(6));
+        assert_eq!(weekday("sundays"), None);
+    }
+
+    #[test]
+    fn from_subjects() {
+        assert_eq!(
+            from_subject("Pay rent @friday", today()),
+            Some(at(10, 23, ::DUE_HOUR))
+        );
+        assert_eq!(
+            from_subject("Taxes DUE:2026-11-01T17:00", today()),
+            Some(at(11, 1, 17))
+        );
+        // The first one that's a date
+        assert_eq!(
+            from_subject("Call @home @tomorrow due:2026-11-01", today()),
+            Some(at(10, 22, ::DUE_HOUR))
+        );
+        assert_eq!(from_subject("Write to me@example.com", today()), None);
+        assert_eq!(from_subject("No date here", today()), None);
+    }
+
+    #[test]
+    fn from_headers() {
+        assert_eq!(from_header(" none ", today()), Some(None));
+        assert_eq!(from_header("None", today()), Some(None));
+        assert_eq!(
+            from_header("2026-11-01T17:00:00+01:00", today()),
+            DateTime::parse_from_rfc3339("2026-11-01T17:00:00+01:00")
+                .ok()
+                .map(Some)
+        );
+        assert_eq!(
+            from_header("tomorrow", today()),
+            Some(Some(at(10, 22, ::DUE_HOUR)))
+        );
+        assert_eq!(from_header("soon", today()), None);
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let due = at(11, 1, 17);
+        let header = to_header(Some(due));
+        assert_eq!(from_header(&header, today()), Some(Some(due)));
+        assert_eq!(from_header(&to_header(None), today()), Some(None));
+    }
+}
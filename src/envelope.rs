@@ -0,0 +1,432 @@
+use chrono::{DateTime, FixedOffset, Local};
+use email::{Address, FromHeader, MimeMessage};
+use regex::Regex;
+
+use priority::{self, Priority};
+use recurrence::Rule;
+use {due, response, tags, Result, Task};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::process;
+
+// UIDs of the tasks whose X-Recurrence was warned about
+thread_local!(
+    static WARNED: RefCell<HashSet<u64>> = RefCell::new(HashSet::new())
+);
+
+// What the server knows about a message besides its headers
+#[derive(Debug, Default)]
+pub struct Meta {
+    pub flags: Vec<String>,
+    // Gmail's, when the server has them
+    pub labels: Vec<String>,
+    pub size: u64,
+    pub received: Option<DateTime<FixedOffset>>,
+}
+
+// Parses the untagged responses to a FETCH (UID FLAGS RFC822.SIZE
+// INTERNALDATE), and X-GM-LABELS on Gmail, indexed by UID. Labels can come
+// as literals, line breaks and all, which the response parser knows about.
+pub fn parse_meta(raw: &[u8]) -> HashMap<u64, Meta> {
+    let mut meta = HashMap::new();
+    for fetch in response::fetches(raw) {
+        let uid = match fetch.get("UID").and_then(|u| u.number()) {
+            Some(uid) => uid,
+            None => continue,
+        };
+        let flags = fetch
+            .get("FLAGS")
+            .map(|f| f.list().iter().filter_map(|f| f.string()).collect())
+            .unwrap_or_default();
+        let labels = fetch
+            .get("X-GM-LABELS")
+            .map(|l| tags::labels(l.list()))
+            .unwrap_or_default();
+        let size = fetch
+            .get("RFC822.SIZE")
+            .and_then(|s| s.number())
+            .unwrap_or(0);
+        let received = fetch
+            .get("INTERNALDATE")
+            .and_then(|d| d.string())
+            .and_then(|d| {
+                DateTime::parse_from_str(d.trim(), "%d-%b-%Y %H:%M:%S %z").ok()
+            });
+        meta.insert(
+            uid,
+            Meta {
+                flags: flags,
+                labels: labels,
+                size: size,
+                received: received,
+            },
+        );
+    }
+    meta
+}
+
+// Tasks come from the headers rather than the ENVELOPE, which has the
+// subject, sender, date and ids but not References, X-Due or X-Recurrence,
+// so those would take the headers anyway
+pub fn build_task(uid: u64, headers: &[u8], meta: Meta) -> Task {
+    // An empty body after the headers makes them a complete message
+    let mut raw = String::from_utf8_lossy(headers).into_owned();
+    raw.push_str("\r\n");
+    let msg = MimeMessage::parse(&raw).unwrap_or_else(|e| {
+        warn!("Couldn't parse headers of {}: {}", uid, e);
+        MimeMessage::new_blank_message()
+    });
+    let header = |name: &str| -> String {
+        msg.headers
+            .get_value::<String>(name.to_string())
+            .unwrap_or_default()
+    };
+
+    let (from_name, from_address) = match msg
+        .headers
+        .get_value::<Vec<Address>>("From".to_string())
+    {
+        Ok(ref addresses) if !addresses.is_empty() => match addresses[0] {
+            Address::Mailbox(ref m) => (
+                m.name
+                    .clone()
+                    .and_then(|n| String::from_header(n).ok())
+                    .unwrap_or_default(),
+                m.address.clone(),
+            ),
+            Address::Group(ref name, _) => (name.clone(), String::new()),
+        },
+        _ => (String::new(), header("From")),
+    };
+
+    let date = msg
+        .headers
+        .get_value::<DateTime<FixedOffset>>("Date".to_string())
+        .ok()
+        .or(meta.received);
+
+    // Relative due dates are relative to when the message was sent
+    let title = header("Subject").trim().to_string();
+    let sent = date.map_or_else(
+        || Local::today().naive_local(),
+        |d| d.with_timezone(&Local).date().naive_local(),
+    );
+    let due = msg.headers
+        .get_value::<String>("X-Due".to_string())
+        .ok()
+        .and_then(|value| due::from_header(&value, sent))
+        .unwrap_or_else(|| due::from_subject(&title, sent));
+    let recurrence = match msg.headers
+        .get_value::<String>("X-Recurrence".to_string())
+    {
+        Ok(value) => Rule::parse(&value).or_else(|| {
+            // Tasks are built again on every sync
+            if WARNED.with(|w| w.borrow_mut().insert(uid)) {
+                warn!("Unsupported X-Recurrence of {}: {}", uid, value);
+            }
+            None
+        }),
+        Err(_) => None,
+    }.or_else(|| Rule::from_subject(&title));
+    let priority_header = msg.headers
+        .get_value::<String>(priority::HEADER.to_string())
+        .ok()
+        .map(|value| value.trim().to_lowercase())
+        .filter(|value| Priority::from_id(value).is_some());
+
+    let mut task = Task {
+        title: title,
+        uid: uid,
+        message_id: message_ids(&header("Message-ID"))
+            .into_iter()
+            .next()
+            .unwrap_or_default(),
+        from_name: from_name,
+        from_address: from_address,
+        date: date,
+        received: meta.received,
+        flags: meta.flags,
+        tags: Vec::new(),
+        in_reply_to: message_ids(&header("In-Reply-To"))
+            .into_iter()
+            .next()
+            .unwrap_or_default(),
+        references: message_ids(&header("References")),
+        parent: None,
+        size: meta.size,
+        due: due,
+        recurrence: recurrence.map(|r| r.to_string()),
+        priority_header: priority_header,
+    };
+    task.tags = tags::collect(&task.keywords(), &meta.labels, &task.title);
+    task
+}
+
+// A new Message-ID without the angle brackets, unique to this moment and
+// process
+pub fn message_id() -> String {
+    format!("{}.{}@mail-todo", Local::now().timestamp_nanos(), process::id())
+}
+
+// The <...> ids in a Message-ID, In-Reply-To or References header, without
+// the angle brackets
+pub fn message_ids(value: &str) -> Vec<String> {
+    let re = Regex::new(r"<([^>]+)>").unwrap();
+    re.captures_iter(value).map(|c| c[1].to_string()).collect()
+}
+
+// The message with a header replaced, or added if it wasn't there, and the
+// rest as it was. The headers are spliced as bytes, so a body that isn't
+// UTF-8 comes out untouched, and the new one ends in the line breaks the
+// others use.
+pub fn set_header(raw: &[u8], name: &str, value: &str) -> Result<Vec<u8>> {
+    Ok(put_header(raw, name, &encode_header(value)?))
+}
+
+// Like set_header, but with the value as it is, for ids like the ones in a
+// Message-ID, where encoded words aren't allowed. It still has to be on a
+// single line.
+pub fn set_raw_header(
+    raw: &[u8],
+    name: &str,
+    value: &str,
+) -> Result<Vec<u8>> {
+    if value.contains(|c| c == '\r' || c == '\n') {
+        return Err(format!("Line breaks in header value {:?}", value).into());
+    }
+    Ok(put_header(raw, name, value))
+}
+
+fn put_header(raw: &[u8], name: &str, value: &str) -> Vec<u8> {
+    let (mut out, body, eol) = without_header(raw, name);
+    let value = value.replace("\r\n", eol);
+    out.extend_from_slice(format!("{}: {}{}", name, value, eol).as_bytes());
+    out.extend_from_slice(body);
+    out
+}
+
+// The message without a header, if it had it
+pub fn remove_header(raw: &[u8], name: &str) -> Vec<u8> {
+    let (mut out, body, _) = without_header(raw, name);
+    out.extend_from_slice(body);
+    out
+}
+
+// The other headers, what comes after them, and the line break they end in
+fn without_header<'a>(
+    raw: &'a [u8],
+    name: &str,
+) -> (Vec<u8>, &'a [u8], &'static str) {
+    let crlf = raw.windows(4).position(|w| w == b"\r\n\r\n");
+    let lf = raw.windows(2).position(|w| w == b"\n\n");
+    let (end, eol) = match (crlf, lf) {
+        (Some(c), Some(l)) if l < c => (l + 1, "\n"),
+        (Some(c), _) => (c + 2, "\r\n"),
+        (None, Some(l)) => (l + 1, "\n"),
+        (None, None) if raw.ends_with(b"\n") && !raw.ends_with(b"\r\n") => {
+            (raw.len(), "\n")
+        }
+        (None, None) => (raw.len(), "\r\n"),
+    };
+    let (headers, body) = raw.split_at(end);
+
+    let prefix = format!("{}:", name.to_lowercase());
+    let mut out = Vec::with_capacity(raw.len());
+    let mut skipping = false;
+    let mut lines: Vec<&[u8]> = headers.split(|&c| c == b'\n').collect();
+    if lines.last().map_or(false, |l| l.is_empty()) {
+        lines.pop();
+    }
+    for line in lines {
+        // Folded lines go on with the previous header
+        if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+            skipping = line.to_ascii_lowercase().starts_with(prefix.as_bytes());
+        }
+        if !skipping {
+            out.extend_from_slice(line);
+            out.push(b'\n');
+        }
+    }
+    (out, body, eol)
+}
+
+// A header value as it can go in a message: on a single line, so it can't
+// add headers of its own, and in RFC 2047 encoded words unless it's ASCII
+pub fn encode_header(value: &str) -> Result<String> {
+    if value.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(format!("Line breaks in header value {:?}", value).into());
+    }
+    if value.is_ascii() {
+        return Ok(value.to_string());
+    }
+
+    // Words are up to 75 characters long, which leaves 45 bytes of text
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in value.chars() {
+        if word.len() + c.len_utf8() > 45 {
+            words.push(format!("=?UTF-8?B?{}?=", base64(word.as_bytes())));
+            word.clear();
+        }
+        word.push(c);
+    }
+    words.push(format!("=?UTF-8?B?{}?=", base64(word.as_bytes())));
+    Ok(words.join("\r\n "))
+}
+
+pub fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (bytes[0] as usize) << 16
+            | (bytes[1] as usize) << 8
+            | bytes[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(CHARS[(n >> (18 - 6 * i)) & 63] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn due(x_due: &str) -> Option<DateTime<FixedOffset>> {
+        let mut headers = String::from(
+            "Subject: Pay rent @friday\r\n\
+             Date: Wed, 21 Oct 2026 12:00:00 +0000\r\n",
+        );
+        if !x_due.is_empty() {
+            headers.push_str(&format!("X-Due: {}\r\n", x_due));
+        }
+        build_task(1, headers.as_bytes(), Meta::default()).due
+    }
+
+    #[test]
+    fn x_due_over_the_subject() {
+        let friday = Local.ymd(2026, 10, 23).and_hms(::DUE_HOUR, 0, 0);
+        assert_eq!(due(""), Some(friday.with_timezone(friday.offset())));
+        assert_eq!(
+            due("2026-11-01T17:00:00+01:00"),
+            DateTime::parse_from_rfc3339("2026-11-01T17:00:00+01:00").ok()
+        );
+        assert_eq!(due("none"), None);
+        // Unless it can't be parsed
+        assert_eq!(due("soon"), Some(friday.with_timezone(friday.offset())));
+    }
+
+    #[test]
+    fn parse_metas() {
+        let raw = b"* 1 FETCH (UID 7 FLAGS (\\Seen $Priority1 work) \
+            RFC822.SIZE 2048 INTERNALDATE \"19-Oct-2026 09:30:00 +0200\" \
+            X-GM-LABELS (\\Inbox {9}\r\nBig\r\nplan \"To \\\"do\\\"\"))\r\n\
+            * 2 FETCH (UID 8 FLAGS ())\r\n\
+            * 2 FETCH (FLAGS (\\Deleted))\r\n\
+            A1 OK Fetch completed\r\n";
+        let meta = parse_meta(raw);
+        assert_eq!(meta.len(), 2);
+        assert_eq!(meta[&7].flags, vec!["\\Seen", "$Priority1", "work"]);
+        assert_eq!(meta[&7].labels, vec!["Big\r\nplan", "To \"do\""]);
+        assert_eq!(meta[&7].size, 2048);
+        assert_eq!(
+            meta[&7].received,
+            DateTime::parse_from_rfc3339("2026-10-19T09:30:00+02:00").ok()
+        );
+        assert!(meta[&8].flags.is_empty());
+    }
+
+    #[test]
+    fn encode_headers() {
+        assert_eq!(encode_header("Pay rent").unwrap(), "Pay rent");
+        assert_eq!(
+            encode_header("Café ☕").unwrap(),
+            "=?UTF-8?B?Q2Fmw6kg4piV?="
+        );
+        assert!(encode_header("Hi\r\nBcc: someone@example.com").is_err());
+        assert!(encode_header("Hi\n\nbody").is_err());
+
+        // Long ones are folded, without splitting characters
+        let title = "Ñ".repeat(40);
+        let encoded = encode_header(&title).unwrap();
+        let words: Vec<&str> = encoded.split("\r\n ").collect();
+        assert_eq!(words.len(), 2);
+        assert!(words.iter().all(|w| w.len() <= 75));
+        let headers = format!("Subject: {}\r\n", encoded);
+        let task = build_task(1, headers.as_bytes(), Meta::default());
+        assert_eq!(task.title, title);
+    }
+
+    #[test]
+    fn set_headers() {
+        let raw = b"Subject: Old\r\nX-Due: none\r\n\r\nBody\r\n";
+        assert_eq!(
+            set_header(raw, "subject", "New").unwrap(),
+            b"X-Due: none\r\nsubject: New\r\n\r\nBody\r\n".to_vec()
+        );
+        assert!(set_header(raw, "Subject", "New\r\nX-Due: today").is_err());
+
+        // Latin-1 in the body stays as it was
+        let raw = b"Subject: Old\n Folded\nFrom: a\n\nCaf\xe9\n";
+        assert_eq!(
+            set_header(raw, "Subject", "Caf\u{e9}").unwrap(),
+            b"From: a\nSubject: =?UTF-8?B?Q2Fmw6k=?=\n\nCaf\xe9\n".to_vec()
+        );
+        assert_eq!(
+            remove_header(raw, "subject"),
+            b"From: a\n\nCaf\xe9\n".to_vec()
+        );
+
+        // Encoded words are folded with the same line breaks
+        let title = "\u{e9}".repeat(30);
+        let set = set_header(raw, "Subject", &title).unwrap();
+        assert!(!set.windows(2).any(|w| w == b"\r\n"));
+        assert_eq!(set.iter().filter(|&&c| c == b'\n').count(), 5);
+    }
+
+    #[test]
+    fn set_raw_headers() {
+        let raw = b"Message-ID: <old@x>\r\n\r\nBody\r\n";
+        assert_eq!(
+            set_raw_header(raw, "Message-ID", "<caf\u{e9}@x>").unwrap(),
+            "Message-ID: <caf\u{e9}@x>\r\n\r\nBody\r\n".as_bytes().to_vec()
+        );
+        assert!(set_raw_header(raw, "Message-ID", "<a@x>\r\nBcc: b").is_err());
+        assert!(set_raw_header(raw, "Message-ID", "<a@x>\nBcc: b").is_err());
+    }
+
+    #[test]
+    fn priority_header() {
+        let headers = b"Subject: Taxes\r\nX-Todo-Priority: Medium\r\n";
+        let task = build_task(1, headers, Meta::default());
+        assert_eq!(task.priority(), Priority::Medium);
+
+        let headers = b"Subject: Taxes\r\nX-Todo-Priority: urgent\r\n";
+        let task = build_task(1, headers, Meta::default());
+        assert_eq!(task.priority_header, None);
+        assert_eq!(task.priority(), Priority::None);
+    }
+
+    #[test]
+    fn encode_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"\0me\0secret"), "AG1lAHNlY3JldA==");
+    }
+}
@@ -0,0 +1,205 @@
+extern crate tiny_http;
+
+use self::tiny_http::{Header, Method, Request, Response, Server};
+use serde_json;
+
+use {control, envelope, threads};
+use {Message, Result, Task};
+
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+const INDEX: &'static str = include_str!("../resources/index.html");
+const MAX_BODY: u64 = 64 * 1024;
+const TOKEN_VAR: &'static str = "MAIL_TODO_HTTP_TOKEN";
+
+type Reply = Response<Cursor<Vec<u8>>>;
+
+#[derive(Deserialize)]
+struct NewTask {
+    title: String,
+}
+
+// The token comes from a file or the environment, as anything on the
+// command line shows up in ps
+pub fn read_token(file: Option<String>) -> Result<String> {
+    let token = match file {
+        Some(file) => {
+            let mut token = String::new();
+            File::open(&file)?.read_to_string(&mut token)?;
+            token
+        }
+        None => env::var(TOKEN_VAR).map_err(|_| {
+            format!("Option 'http' requires 'http-token-file' or {}", TOKEN_VAR)
+        })?,
+    };
+    let token = token.trim().to_string();
+    if token.is_empty() {
+        return Err("The HTTP token is empty".into());
+    }
+    Ok(token)
+}
+
+pub fn start(
+    addr: &str,
+    token: &str,
+    poller: Sender<Message>,
+    rx: Receiver<Message>,
+) {
+    let server = match Server::http(addr) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Couldn't start HTTP server on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving HTTP on {}", addr);
+
+    let mut tasks: HashSet<Task> = HashSet::new();
+    'outer: loop {
+        while let Ok(m) = rx.try_recv() {
+            match m {
+                Message::Quit => break 'outer,
+                Message::Tasks(t) => tasks = t,
+                _ => {}
+            }
+        }
+
+        match server.recv_timeout(Duration::new(1, 0)) {
+            Ok(Some(request)) => handle(request, token, &tasks, &poller),
+            Ok(None) => {}
+            Err(e) => error!("Error receiving HTTP request: {}", e),
+        }
+    }
+    info!("Exiting HTTP thread");
+}
+
+fn handle(
+    mut request: Request,
+    token: &str,
+    tasks: &HashSet<Task>,
+    poller: &Sender<Message>,
+) {
+    debug!("HTTP {} {}", request.method(), request.url());
+
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    let segments: Vec<&str> =
+        path.split('/').filter(|s| !s.is_empty()).collect();
+
+    // The page itself holds no data, so it's served without a token
+    if let (&Method::Get, true) = (request.method(), segments.is_empty()) {
+        let _ = request.respond(
+            Response::from_string(INDEX).with_header(content_type("text/html")),
+        );
+        return;
+    }
+
+    if !authorized(&request, token) {
+        let _ = request.respond(error(401, "Missing or bad token"));
+        return;
+    }
+
+    let response = match (request.method().clone(), &segments[..]) {
+        (Method::Get, ["tasks"]) => json(200, &control::sorted(tasks)),
+        (Method::Post, ["tasks"]) => {
+            // One more byte than allowed tells a body that's too big
+            let mut body = String::new();
+            let read = request
+                .as_reader()
+                .take(MAX_BODY + 1)
+                .read_to_string(&mut body);
+            match read.map(|_| serde_json::from_str::<NewTask>(&body)) {
+                _ if body.len() as u64 > MAX_BODY => {
+                    error(413, "Body too large")
+                }
+                Err(e) => error(400, &e.to_string()),
+                Ok(Err(e)) => error(400, &e.to_string()),
+                Ok(Ok(ref t)) if t.title.trim().is_empty() => {
+                    error(400, "Empty title")
+                }
+                Ok(Ok(t)) => match envelope::encode_header(&t.title) {
+                    Ok(_) => {
+                        let id = envelope::message_id();
+                        forward(poller, Message::Add(t.title, id))
+                    }
+                    Err(e) => error(400, &e.to_string()),
+                },
+            }
+        }
+        (Method::Delete, ["tasks", uid]) => match uid.parse::<u64>() {
+            // Threads are completed as a whole, as from the window
+            Ok(uid) if tasks.iter().any(|t| t.uid == uid) => {
+                let uids = threads::with_replies(tasks, uid);
+                forward_all(poller, uids.into_iter().map(Message::Delete))
+            }
+            _ => error(404, "No such task"),
+        },
+        (Method::Post, ["refresh"]) => forward(poller, Message::Refresh),
+        _ => error(404, "Not found"),
+    };
+    let _ = request.respond(response);
+}
+
+fn authorized(request: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    request.headers().iter().any(|h| {
+        h.field.equiv("Authorization")
+            && same(h.value.as_str().as_bytes(), expected.as_bytes())
+    })
+}
+
+// Goes through every byte whatever they are, so the time it takes doesn't
+// tell how much of the token was right
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0, |diff, (x, y)| diff | x ^ y) == 0
+}
+
+fn forward(tx: &Sender<Message>, msg: Message) -> Reply {
+    forward_all(tx, Some(msg))
+}
+
+// Stops at the first message that can't be sent
+fn forward_all<I>(tx: &Sender<Message>, msgs: I) -> Reply
+where
+    I: IntoIterator<Item = Message>,
+{
+    for msg in msgs {
+        if let Err(e) = tx.send(msg) {
+            return error(503, &e.to_string());
+        }
+    }
+    json(202, &json!({"status": "accepted"}))
+}
+
+fn error(code: u16, message: &str) -> Reply {
+    json(code, &json!({ "error": message }))
+}
+
+fn json<T: ::serde::Serialize>(code: u16, value: &T) -> Reply {
+    let body = serde_json::to_string(value).unwrap_or_default();
+    Response::from_string(body)
+        .with_status_code(code)
+        .with_header(content_type("application/json"))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_tokens() {
+        assert!(same(b"Bearer s3cret", b"Bearer s3cret"));
+        assert!(!same(b"Bearer s3cret", b"Bearer s3creT"));
+        assert!(!same(b"Bearer s3cret", b"Bearer s3cre"));
+        assert!(!same(b"", b"Bearer s3cret"));
+    }
+}
@@ -0,0 +1,87 @@
+extern crate dirs;
+extern crate libc;
+
+use control::{self, Request, Response};
+use Result;
+
+use std::env;
+use std::ffi::{CString, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread::sleep;
+use std::time::Duration;
+
+// A file in the runtime directory, or ~/.mail-todo/ without one. Never the
+// shared temporary directory, where anyone could make it first.
+pub fn runtime_path(name: &str) -> Result<PathBuf> {
+    let mut path = runtime_dir()?;
+    path.push(name);
+    Ok(path)
+}
+
+fn runtime_dir() -> Result<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => {
+            let mut path = dirs::home_dir().ok_or("Can't get home dir")?;
+            path.push(".mail-todo");
+            fs::create_dir_all(&path)?;
+            Ok(path)
+        }
+    }
+}
+
+// A new directory with a random name that only the user can get into, for
+// files others shouldn't be able to swap or read, in the runtime directory
+pub fn private_dir(prefix: &str) -> Result<PathBuf> {
+    let mut template = runtime_dir()?;
+    template.push(format!("{}-XXXXXX", prefix));
+
+    // mkdtemp fills in the Xs and creates the directory as 0700
+    let template = CString::new(template.as_os_str().as_bytes())?.into_raw();
+    let ret = unsafe { libc::mkdtemp(template) };
+    let template = unsafe { CString::from_raw(template) };
+    if ret.is_null() {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(PathBuf::from(OsString::from_vec(template.into_bytes())))
+}
+
+// Returns None when another instance already holds the lock. The lock lasts
+// as long as the returned file is kept open, and the kernel releases it if
+// the process dies, so there are no stale locks to clean up.
+pub fn lock(path: &Path) -> Result<Option<File>> {
+    let mut file = OpenOptions::new().create(true).write(true).open(path)?;
+    let ret =
+        unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret != 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+            return Ok(None);
+        }
+        return Err(err.into());
+    }
+
+    file.set_len(0)?;
+    writeln!(file, "{}", process::id())?;
+    Ok(Some(file))
+}
+
+// Ask the running instance to show its window. It may still be starting up
+// and not listening yet, so give it a few chances.
+pub fn activate(socket: &Path) -> Result<()> {
+    for _ in 0..10 {
+        if let Some(mut stream) = control::connect(socket) {
+            return match control::request(&mut stream, &Request::Present)? {
+                Response::Error { message } => Err(message.into()),
+                _ => Ok(()),
+            };
+        }
+        sleep(Duration::from_millis(500));
+    }
+    Err("Another instance holds the lock but isn't answering".into())
+}
@@ -0,0 +1,91 @@
+use {Result, Task};
+
+use std::path::Path;
+use std::process::{Child, Command};
+use std::thread;
+
+// Runs the mail client command for a task. The command is split into
+// words the way a shell would, and only then are {msgid}, {folder} and
+// {uid} replaced, so their values never need any quoting.
+pub fn open(template: &str, task: &Task, folder: &str) -> Result<()> {
+    if task.message_id.is_empty() && template.contains("{msgid}") {
+        return Err("The message has no Message-ID".into());
+    }
+
+    let words: Vec<String> = split(template)?
+        .iter()
+        .map(|w| {
+            w.replace("{msgid}", &task.message_id)
+                .replace("{folder}", folder)
+                .replace("{uid}", &task.uid.to_string())
+        })
+        .collect();
+    let (program, args) = words.split_first().ok_or("Empty command")?;
+
+    info!("Running {:?}", words);
+    reap(Command::new(program).args(args).spawn()?);
+    Ok(())
+}
+
+pub fn open_file(path: &Path) -> Result<()> {
+    reap(Command::new("xdg-open").arg(path).spawn()?);
+    Ok(())
+}
+
+// Wait for it whenever it finishes, so it doesn't linger as a zombie
+fn reap(mut child: Child) {
+    thread::spawn(move || child.wait());
+}
+
+pub fn split(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated ' quote".into()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err("Trailing \\".into()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated \" quote".into()),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.push(chars.next().ok_or("Trailing \\")?);
+            }
+            c if c.is_whitespace() => if in_word {
+                words.push(word.clone());
+                word.clear();
+                in_word = false;
+            },
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
@@ -1,42 +1,175 @@
+extern crate chrono;
 extern crate email;
+extern crate encoding;
 extern crate imap;
 extern crate native_tls;
 extern crate notify_rust;
 extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 
 #[macro_use]
 extern crate log;
 
 pub mod backup;
+pub mod cache;
+pub mod cli;
+pub mod control;
+pub mod details;
+pub mod digest;
+pub mod due;
+pub mod envelope;
+pub mod http;
+pub mod instance;
+pub mod launcher;
 pub mod notifier;
 pub mod parser;
 pub mod poller;
+pub mod priority;
+pub mod quiet;
+pub mod recurrence;
+pub mod reminder;
+pub mod response;
+pub mod search;
+pub mod smtp;
+pub mod tags;
+pub mod threads;
+pub mod view;
 
+pub const CACHE: &'static str = ".mail-todo/cache";
+pub const COMPLETED: &'static str = ".mail-todo/completed.json";
 pub const DB: &'static str = ".mail-todo/todo.db";
+pub const DIGEST: &'static str = ".mail-todo/digest.json";
+pub const DIGEST_AT: &'static str = "08:00";
+pub const DIGEST_RETRY: i64 = 10;
+pub const DONE: &'static str = "Done";
+pub const DUE_HOUR: u32 = 9;
 pub const ICON: &'static str = "task-due";
+pub const LOCK: &'static str = "mail-todo.lock";
+pub const MAIL_CLIENT: &'static str = "xdg-open mid:{msgid}";
 pub const MBOX: &'static str = "ToDo";
+pub const NAG: &'static str = "1h";
 pub const NAME: &'static str = "Mail-todo";
 pub const NOTIF_TIMEOUT: i32 = 5000;
+pub const REMIND: &'static str = "1d,1h";
 pub const SLEEP: u64 = 60;
+pub const SNOOZE: i64 = 60;
+pub const SHORTCUTS: &'static str = ".mail-todo/shortcuts.json";
+pub const SOCKET: &'static str = "mail-todo.sock";
+pub const UNDO_GRACE: u32 = 5;
+pub const VIEW: &'static str = ".mail-todo/view.json";
+
+use chrono::{DateTime, FixedOffset, Local};
 
 pub type Result<T> = std::result::Result<T, Box<std::error::Error>>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Message {
+    // A task with that title and Message-ID, made up by whoever asks for it
+    // with envelope::message_id so it can tell the task when it shows up
+    Add(String, String),
+    AddTag(u64, String),
+    AskTask,
     Awake,
+    Complete(u64),
+    Completed(u64),
+    // A task the poller couldn't complete, and why
+    CompleteFailed(u64, String),
     Connect,
     Connected,
     Delete(u64),
+    Details(details::Details),
+    // When the digest asked for was sent, to whoever asked for it
+    DigestSent(DateTime<Local>),
+    Edit(u64, String),
+    Fetch(u64),
+    Found(std::collections::HashMap<String, std::collections::HashSet<u64>>),
     NotConnected,
+    Open(u64),
+    Present,
+    Refresh,
+    RemoveTag(u64, String),
+    // A task replaced by a copy with that Message-ID, by the poller on its
+    // own
+    Replaced(u64, String),
+    Restore(String),
+    SaveAttachment(u64, usize, Option<std::path::PathBuf>),
+    SendDigest(digest::Request),
+    Saved(std::path::PathBuf),
+    Search(Vec<String>),
+    SetDue(u64, Option<DateTime<FixedOffset>>),
+    SetPriority(u64, priority::Priority),
+    Snooze(u64),
     Sleep,
+    SyncError(String),
+    Syncing,
     Tasks(std::collections::HashSet<Task>),
+    Toggle,
+    TrayFallback,
     Quit,
 }
 
-#[derive(Clone, Hash, Eq, PartialEq, Debug)]
+#[derive(Clone, Hash, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Task {
     pub title: String,
     pub uid: u64,
+    // Message ids are kept without the angle brackets
+    pub message_id: String,
+    pub from_name: String,
+    pub from_address: String,
+    // Date header, or when the server got it if there's none
+    pub date: Option<DateTime<FixedOffset>>,
+    pub received: Option<DateTime<FixedOffset>>,
+    pub flags: Vec<String>,
+    // Keywords, Gmail labels and #hashtags in the title
+    pub tags: Vec<String>,
+    pub in_reply_to: String,
+    pub references: Vec<String>,
+    // The task that started its thread, for replies to one in the folder
+    pub parent: Option<u64>,
+    pub size: u64,
+    pub due: Option<DateTime<FixedOffset>>,
+    // RRULE of a recurring task, without the "RRULE:"
+    pub recurrence: Option<String>,
+    // Id of the priority in the X-Todo-Priority header, if it has one
+    pub priority_header: Option<String>,
+}
+
+impl Task {
+    pub fn sender(&self) -> &str {
+        if self.from_name.is_empty() {
+            &self.from_address
+        } else {
+            &self.from_name
+        }
+    }
+
+    // IMAP keywords, leaving out system flags and the ones clients use as
+    // flags of their own, like $Forwarded or NonJunk
+    pub fn keywords(&self) -> Vec<&str> {
+        self.flags
+            .iter()
+            .filter(|f| !f.starts_with('\\') && !tags::is_client_keyword(f))
+            .map(|f| &f[..])
+            .collect()
+    }
+
+    pub fn priority(&self) -> priority::Priority {
+        let header = self.priority_header
+            .as_ref()
+            .and_then(|p| priority::Priority::from_id(p))
+            .unwrap_or_default();
+        priority::Priority::of(&self.flags).max(header)
+    }
+
+    pub fn recurrence(&self) -> Option<recurrence::Rule> {
+        self.recurrence
+            .as_ref()
+            .and_then(|r| recurrence::Rule::parse(r))
+    }
 }
 
 #[derive(Debug)]
@@ -45,4 +178,8 @@ pub struct Creds {
     pub pass: String,
     pub host: String,
     pub port: u16,
+    // mutt's from, smtp_url and smtp_pass, to send mail
+    pub from: Option<String>,
+    pub smtp_url: Option<String>,
+    pub smtp_pass: Option<String>,
 }
@@ -1,30 +1,181 @@
 extern crate getopts;
 use getopts::Options;
 
+extern crate gdk;
 extern crate gtk;
 use gtk::prelude::*;
-use gtk::{Builder, Button, CheckButton, ListBox, ListBoxRow, StatusIcon,
-          Statusbar, Window};
+use gtk::{Builder, Button, Calendar, CheckButton, ComboBoxText, CssProvider,
+          Dialog, Entry, FileChooserDialog, Label, ListBox, ListBoxRow,
+          SearchBar, SearchEntry, Statusbar, StyleContext, TextView,
+          ToggleButton, Window};
 
 extern crate glib;
 
+extern crate chrono;
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, Timelike};
+
 #[macro_use]
 extern crate log;
 extern crate env_logger;
 
 extern crate mail_todo;
-use mail_todo::{backup, notifier, parser, poller, Message, Task};
+use mail_todo::details::{self, Details};
+use mail_todo::digest::{self, Via};
+use mail_todo::notifier::{self, Changes, Kind, Note, Notifier};
+use mail_todo::priority::Priority;
+use mail_todo::quiet::{self, Quiet};
+use mail_todo::reminder::{self, Scheduler, SystemClock};
+use mail_todo::search::{self, Query};
+use mail_todo::view::{self, Group, Sort, View};
+use mail_todo::{backup, cache, cli, control, due, envelope, http,
+                instance, launcher, parser, poller, threads, Message, Task};
 
-use std::cell::RefCell;
-use std::collections::HashSet;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
 
+mod shortcuts;
+use shortcuts::{Action, Shortcut};
+
+mod tray;
+use tray::Tray;
+
+// Response ids of the "Add", "Save", "Undo", "OK" and "Clear" buttons in
+// their dialogs and bars
+const ADD: i32 = 1;
+const SAVE: i32 = 1;
+const UNDO: i32 = 1;
+const SET_DUE: i32 = 1;
+const CLEAR_DUE: i32 = 2;
+
+// How many completed tasks can be brought back
+const UNDO_HISTORY: usize = 20;
+
+// Seconds between checks for reminders to show
+const REMIND_CHECK: u32 = 30;
+
+// Seconds changes to the folder are gathered before notifying them
+const COALESCE: u32 = 2;
+
+// Tags look like chips
+const CSS: &'static str = ".tag {
+    border-radius: 9px;
+    padding: 0 6px;
+    background-color: alpha(@theme_fg_color, 0.1);
+    font-size: smaller;
+}";
+
+struct Ui {
+    builder: Builder,
+    poller: Sender<Message>,
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+    tray: Tray,
+    stoppers: Vec<Sender<Message>>,
+    // Shared with the list's sort function, which can't reach GLOBAL as
+    // it runs while rows are added
+    tasks: Rc<RefCell<HashMap<u64, Task>>>,
+    view: Rc<RefCell<View>>,
+    filter: Rc<RefCell<Filter>>,
+    // Completed tasks still within the grace period, the batch their timer
+    // belongs to, and the ones already moved to the done folder
+    pending: Vec<Task>,
+    batch: u32,
+    done: Vec<Task>,
+    // Message-IDs of the copies recurring tasks in done got
+    copies: HashMap<u64, String>,
+    grace: u32,
+    shortcuts: Vec<Shortcut>,
+    sync: SyncStatus,
+    reminders: Scheduler<SystemClock>,
+    notifiers: Vec<Box<Notifier>>,
+    quiet: Quiet,
+    // Whether the desktop is in do not disturb mode
+    dnd: Arc<AtomicBool>,
+    // Changes waiting to be notified, the ones made from here, which
+    // aren't, and the tasks the poller completed, from here or not
+    changes: Changes,
+    own: Own,
+    completed: HashSet<u64>,
+    // Task being edited in the add dialog, if that's what it's doing
+    editing: Rc<Cell<Option<u64>>>,
+    folder: String,
+    mail_client: String,
+}
+
+// Which tasks are left out of the list: the ones not matching the search,
+// the ones completed here that the server still has, and the replies in
+// threads that aren't expanded
+#[derive(Default)]
+struct Filter {
+    query: Query,
+    // Results of searching the bodies on the server, by term
+    found: HashMap<String, HashSet<u64>>,
+    visible: Option<HashSet<u64>>,
+    completed: HashSet<u64>,
+    // Roots of the threads showing their replies
+    expanded: HashSet<u64>,
+    // Bodies of the tasks, or None when they aren't cached, read once
+    // rather than on every keystroke of a search
+    bodies: HashMap<u64, Option<String>>,
+}
+
+// Tasks completed or replaced from the window, and Message-IDs of the ones
+// added or replacing others, expected to show up in the next sync. That
+// may be the one after it, if the poller was syncing already, and after
+// that they're forgotten, as they may never show up if something failed.
+#[derive(Default)]
+struct Own {
+    // With how many syncs went by since
+    removed: HashMap<u64, u32>,
+    added: HashMap<String, u32>,
+}
+
+impl Own {
+    fn add(&mut self, message_id: &str) {
+        if !message_id.is_empty() {
+            self.added.insert(message_id.to_string(), 0);
+        }
+    }
+
+    fn remove(&mut self, uid: u64) {
+        self.removed.insert(uid, 0);
+    }
+
+    // Edits keep the Message-ID
+    fn replace(&mut self, task: &Task) {
+        self.remove(task.uid);
+        self.add(&task.message_id);
+    }
+
+    fn synced(&mut self) {
+        self.removed.retain(|_, syncs| {
+            *syncs += 1;
+            *syncs < 2
+        });
+        self.added.retain(|_, syncs| {
+            *syncs += 1;
+            *syncs < 2
+        });
+    }
+}
+
+// What the status bar tells about the poller
+#[derive(Default)]
+struct SyncStatus {
+    connected: bool,
+    syncing: bool,
+    last: Option<DateTime<Local>>,
+    error: Option<String>,
+}
+
 thread_local!(
-    static GLOBAL: RefCell<
-        Option<(Builder, Sender<Message>, Receiver<Message>)>
-    > = RefCell::new(None)
+    static GLOBAL: RefCell<Option<Ui>> = RefCell::new(None)
 );
 
 fn main() {
@@ -33,7 +184,7 @@ fn main() {
 
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
-    opts.reqopt(
+    opts.optopt(
         "c",
         "config",
         "Path to the config file",
@@ -45,6 +196,94 @@ fn main() {
         "IMAP folder to monitor",
         "FOLDER",
     );
+    opts.optopt(
+        "",
+        "done-folder",
+        "IMAP folder completed tasks are moved to, so they can be restored, \
+         or '' to delete them (default: Done)",
+        "FOLDER",
+    );
+    opts.optopt(
+        "",
+        "undo-grace",
+        "Seconds a completion can be undone before it reaches the server \
+         (default: 5)",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "remind",
+        "How long before their due date to remind of tasks, besides when \
+         they're due, e.g. 2d,30m (default: 1d,1h)",
+        "TIMES",
+    );
+    opts.optopt(
+        "",
+        "nag",
+        "How often to remind of overdue tasks, or 0 for never (default: 1h)",
+        "TIME",
+    );
+    opts.optmulti(
+        "",
+        "notify",
+        "Where notifications go: desktop, log (stderr), command:COMMAND \
+         (given MAIL_TODO_* variables and JSON on stdin) or webhook:URL \
+         (POSTed the JSON). Can be repeated (default: desktop)",
+        "NOTIFIER",
+    );
+    opts.optopt(
+        "",
+        "quiet-hours",
+        "Hours notifications are held back and then delivered all at once, \
+         e.g. 22:00-07:00",
+        "START-END",
+    );
+    opts.optopt(
+        "",
+        "quiet-days",
+        "Days notifications are held back all day, e.g. sat,sun",
+        "DAYS",
+    );
+    opts.optopt(
+        "",
+        "digest",
+        "Mail a digest of the pending, overdue and completed tasks daily or \
+         weekly (on Mondays)",
+        "daily|weekly",
+    );
+    opts.optopt(
+        "",
+        "digest-at",
+        "Time to send the digest (default: 08:00)",
+        "HH:MM",
+    );
+    opts.optopt(
+        "",
+        "digest-to",
+        "Send the digest to this address through the config's smtp_url, \
+         instead of adding it to the INBOX",
+        "ADDRESS",
+    );
+    opts.optopt(
+        "",
+        "mail-client",
+        "Command opening a task's email, where {msgid}, {folder} and {uid} \
+         are replaced (default: xdg-open mid:{msgid})",
+        "COMMAND",
+    );
+    opts.optopt(
+        "",
+        "http",
+        "Serve the HTTP API and web view on this address",
+        "ADDR:PORT",
+    );
+    opts.optopt(
+        "",
+        "http-token-file",
+        "File with the token HTTP clients must send as 'Authorization: \
+         Bearer TOKEN' (default: the MAIL_TODO_HTTP_TOKEN variable)",
+        "FILE",
+    );
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -57,84 +296,669 @@ fn main() {
         print_usage(&program, opts);
         return;
     }
-    let conf = matches.opt_str("c").unwrap();
     let folder = if matches.opt_present("f") {
         matches.opt_str("f").unwrap()
     } else {
         String::from(mail_todo::MBOX)
     };
 
+    let done = matches
+        .opt_str("done-folder")
+        .unwrap_or_else(|| String::from(mail_todo::DONE));
+
+    env_logger::init();
+
+    if !matches.free.is_empty() {
+        let creds = matches
+            .opt_str("c")
+            .and_then(|c| parser::get_credentials(c).ok());
+        if let Err(e) = cli::run(&matches.free, creds, &folder, &done) {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let conf = match matches.opt_str("c") {
+        Some(c) => c,
+        None => {
+            println!("Argument to option 'c' missing.");
+            print_usage(&program, opts);
+            return;
+        }
+    };
+    let grace = match matches.opt_str("undo-grace").map(|g| g.parse()) {
+        None => mail_todo::UNDO_GRACE,
+        Some(Ok(grace)) => grace,
+        Some(Err(_)) => {
+            println!("Option 'undo-grace' takes a number of seconds.");
+            return;
+        }
+    };
+    let leads = matches
+        .opt_str("remind")
+        .unwrap_or_else(|| String::from(mail_todo::REMIND));
+    let leads = match reminder::parse_leads(&leads) {
+        Some(leads) => leads,
+        None => {
+            println!("Option 'remind' takes times like 1d, 2h or 30m.");
+            return;
+        }
+    };
+    let nag = matches
+        .opt_str("nag")
+        .unwrap_or_else(|| String::from(mail_todo::NAG));
+    let nag = match reminder::parse_duration(&nag) {
+        Some(nag) => Some(nag),
+        None => {
+            println!("Option 'nag' takes a time like 1d, 2h or 30m.");
+            return;
+        }
+    };
+    let hours = matches.opt_str("quiet-hours");
+    let hours = match hours.as_ref().map(|h| quiet::parse_hours(h)) {
+        None => None,
+        Some(Some(hours)) => Some(hours),
+        Some(None) => {
+            println!("Option 'quiet-hours' takes times like 22:00-07:00.");
+            return;
+        }
+    };
+    let days = matches.opt_str("quiet-days");
+    let days = match days.as_ref().map(|d| quiet::parse_days(d)) {
+        None => Vec::new(),
+        Some(Some(days)) => days,
+        Some(None) => {
+            println!("Option 'quiet-days' takes days like sat,sun.");
+            return;
+        }
+    };
+    let quiet = Quiet::new(hours, days);
+    let digest = match matches.opt_str("digest") {
+        Some(every) => {
+            let at = matches
+                .opt_str("digest-at")
+                .unwrap_or_else(|| String::from(mail_todo::DIGEST_AT));
+            match digest::parse_schedule(&every, &at) {
+                Some(schedule) => {
+                    let via = match matches.opt_str("digest-to") {
+                        Some(to) => Via::Smtp(to),
+                        None => Via::Append(String::from("INBOX")),
+                    };
+                    Some((schedule, via))
+                }
+                None => {
+                    println!(
+                        "Option 'digest' takes daily or weekly, and \
+                         'digest-at' a time like 08:00."
+                    );
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let mail_client = matches
+        .opt_str("mail-client")
+        .unwrap_or_else(|| String::from(mail_todo::MAIL_CLIENT));
+    let http = match matches.opt_str("http") {
+        Some(addr) => {
+            let file = matches.opt_str("http-token-file");
+            match http::read_token(file) {
+                Ok(token) => Some((addr, token)),
+                Err(e) => {
+                    println!("Can't read the HTTP token: {}", e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    // A second instance would poll and expunge the same folder and show a
+    // second icon, so just bring up the window of the first one instead.
+    // The lock must be held until we exit.
+    let lock = instance::runtime_path(mail_todo::LOCK)
+        .and_then(|path| instance::lock(&path));
+    let _lock = match lock {
+        Ok(Some(lock)) => Some(lock),
+        Ok(None) => {
+            info!("Already running, activating the existing instance");
+            let activated = control::socket_path()
+                .and_then(|socket| instance::activate(&socket));
+            if let Err(e) = activated {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Err(e) => {
+            warn!("Couldn't take the instance lock: {}", e);
+            None
+        }
+    };
+
     if let Err(e) = gtk::init() {
         panic!("Failed to initialize GTK: {:?}", e);
     }
 
-    env_logger::init();
-
     let (backup_tx, backup_rx) = channel::<Message>();
+    let (control_tx, control_rx) = channel::<Message>();
     let (imap_tx, imap_rx) = channel::<Message>();
     let (ui_tx, ui_rx) = channel::<Message>();
 
+    // Threads interested in task updates from the poller, and threads to
+    // be told to quit when the window closes
+    let control_ui = ui_tx.clone();
+    let tray_ui = ui_tx.clone();
+
+    let mut specs = matches.opt_strs("notify");
+    if specs.is_empty() {
+        specs.push(String::from("desktop"));
+    }
+    let mut notifiers = Vec::new();
+    for spec in specs {
+        match notifier::parse(&spec, &tray_ui) {
+            Ok(notifier) => notifiers.push(notifier),
+            Err(e) => {
+                println!("Option 'notify': {}", e);
+                return;
+            }
+        }
+    }
+    let mut listeners = vec![ui_tx, control_tx.clone()];
+    let mut stoppers = vec![imap_tx.clone(), backup_tx.clone(), control_tx];
+
+    let mut threads = Vec::new();
+    if let Some((addr, token)) = http {
+        let (http_tx, http_rx) = channel::<Message>();
+        listeners.push(http_tx.clone());
+        stoppers.push(http_tx);
+        let http_poller = imap_tx.clone();
+        threads.push(
+            thread::Builder::new()
+                .name("http".to_string())
+                .spawn(move || {
+                    http::start(&addr, &token, http_poller, http_rx);
+                })
+                .unwrap(),
+        );
+    }
+
     let ui = include_str!("../resources/ui.glade");
     let builder = Builder::new_from_string(ui);
+    let css = CssProvider::new();
+    match (css.load_from_data(CSS.as_bytes()), gdk::Screen::get_default()) {
+        (Ok(()), Some(screen)) => StyleContext::add_provider_for_screen(
+            &screen,
+            &css,
+            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+        ),
+        (Err(e), _) => warn!("Couldn't load the style: {}", e),
+        (_, None) => warn!("No screen to style"),
+    }
 
-    let stop_poller = imap_tx.clone();
-    let stop_backup = backup_tx.clone();
     let window: Window = builder.get_object("window").unwrap();
-    window.connect_delete_event(move |_, _| {
-        info!("Closing...");
-        let _ = stop_poller.send(Message::Quit).unwrap();
-        let _ = stop_backup.send(Message::Quit).unwrap();
-        gtk::main_quit();
+    window.connect_delete_event(|_, _| {
+        GLOBAL.with(|global| {
+            if let Some(ref mut ui) = *global.borrow_mut() {
+                quit(ui);
+            }
+        });
         Inhibit(false)
     });
 
-    let icon = StatusIcon::new_from_icon_name(mail_todo::ICON);
-    icon.connect_activate(move |_| {
-        window.set_visible(!window.is_visible());
-    });
-
     let del: Button = builder.get_object("delete").unwrap();
     del.connect_clicked(|_| {
-        destroy_checked();
+        GLOBAL.with(|global| {
+            if let Some(ref mut ui) = *global.borrow_mut() {
+                complete_checked(ui);
+            }
+        });
     });
 
-    let imap_tx2 = imap_tx.clone();
-    GLOBAL.with(move |global| {
-        *global.borrow_mut() = Some((builder, imap_tx2, ui_rx))
+    let refresh: Button = builder.get_object("refresh").unwrap();
+    let refresh_poller = imap_tx.clone();
+    refresh.connect_clicked(move |_| {
+        let _ = refresh_poller.send(Message::Refresh);
     });
+
+    let undo_button: Button = builder.get_object("undo").unwrap();
+    undo_button.connect_clicked(|_| {
+        GLOBAL.with(|global| {
+            if let Some(ref mut ui) = *global.borrow_mut() {
+                undo(ui);
+            }
+        });
+    });
+    let undo_bar: gtk::InfoBar = builder.get_object("undo_bar").unwrap();
+    undo_bar.connect_response(|bar, response| {
+        if response != UNDO {
+            bar.hide();
+            return;
+        }
+        GLOBAL.with(|global| {
+            if let Some(ref mut ui) = *global.borrow_mut() {
+                undo(ui);
+            }
+        });
+    });
+
+    window.connect_key_press_event(|window, event| {
+        GLOBAL.with(|global| {
+            if let Some(ref mut ui) = *global.borrow_mut() {
+                let key = event.get_keyval();
+                let state = event.get_state();
+                if let Some(action) =
+                    shortcuts::find(&ui.shortcuts, window, key, state)
+                {
+                    run_action(ui, action);
+                    return Inhibit(true);
+                }
+            }
+            Inhibit(false)
+        })
+    });
+
+    let dialog: Dialog = builder.get_object("add_dialog").unwrap();
+    let entry: Entry = builder.get_object("add_entry").unwrap();
+    let add_poller = imap_tx.clone();
+    let editing = Rc::new(Cell::new(None));
+    let add_editing = editing.clone();
+    dialog.connect_response(move |dialog, response| {
+        if response == ADD {
+            let title = entry.get_text().unwrap_or_default();
+            let title = title.trim().to_string();
+            let id = envelope::message_id();
+            let msg = match add_editing.get() {
+                Some(uid) => Message::Edit(uid, title.clone()),
+                None => Message::Add(title.clone(), id.clone()),
+            };
+            if !title.is_empty() {
+                GLOBAL.with(|global| {
+                    if let Some(ref mut ui) = *global.borrow_mut() {
+                        let tasks = ui.tasks.borrow();
+                        match add_editing.get().and_then(|u| tasks.get(&u)) {
+                            Some(task) => ui.own.replace(task),
+                            None => ui.own.add(&id),
+                        }
+                    }
+                });
+                let _ = add_poller.send(msg);
+            }
+        }
+        add_editing.set(None);
+        entry.set_text("");
+        dialog.hide();
+    });
+    dialog.connect_delete_event(|dialog, _| {
+        dialog.hide();
+        Inhibit(true)
+    });
+
+    let due_dialog: Dialog = builder.get_object("due_dialog").unwrap();
+    let due_ui = builder.clone();
+    let due_poller = imap_tx.clone();
+    due_dialog.connect_response(move |dialog, response| {
+        let lb: ListBox = due_ui.get_object("content").unwrap();
+        let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+        let change = match (uid, response) {
+            (Some(uid), SET_DUE) => match chosen_due(&due_ui) {
+                Some(date) => Some((uid, Some(date))),
+                None => {
+                    let time: Entry = due_ui.get_object("due_time").unwrap();
+                    time.grab_focus();
+                    return;
+                }
+            },
+            (Some(uid), CLEAR_DUE) => Some((uid, None)),
+            _ => None,
+        };
+        if let Some((uid, date)) = change {
+            // The task is replaced by a copy
+            GLOBAL.with(|global| {
+                if let Some(ref mut ui) = *global.borrow_mut() {
+                    if let Some(task) = ui.tasks.borrow().get(&uid) {
+                        ui.own.replace(task);
+                    }
+                }
+            });
+            let _ = due_poller.send(Message::SetDue(uid, date));
+        }
+        dialog.hide();
+    });
+    due_dialog.connect_delete_event(|dialog, _| {
+        dialog.hide();
+        Inhibit(true)
+    });
+    let due_button: Button = builder.get_object("due").unwrap();
+    due_button.connect_clicked(|_| {
+        GLOBAL.with(|global| {
+            if let Some(ref mut ui) = *global.borrow_mut() {
+                run_action(ui, Action::Due);
+            }
+        });
+    });
+
+    let tasks = Rc::new(RefCell::new(HashMap::new()));
+    let filter = Rc::new(RefCell::new(Filter::default()));
+    let lb: ListBox = builder.get_object("content").unwrap();
+    let select_ui = builder.clone();
+    let select_poller = imap_tx.clone();
+    let select_folder = folder.clone();
+    let select_tasks = tasks.clone();
+    let select_filter = filter.clone();
+    lb.connect_row_selected(move |_, row| {
+        match row.as_ref().and_then(row_uid) {
+            Some(uid) => {
+                show_task(&select_ui, &select_folder, uid, &select_poller);
+                let tasks = select_tasks.borrow();
+                let filter = select_filter.borrow();
+                show_flags(&select_ui, &tasks, &filter, &select_poller);
+            }
+            None => {
+                let detail: gtk::Box = select_ui.get_object("detail").unwrap();
+                detail.hide();
+            }
+        }
+    });
+
+    let priority: ComboBoxText =
+        builder.get_object("detail_priority").unwrap();
+    let priority_ui = builder.clone();
+    let priority_tasks = tasks.clone();
+    let priority_poller = imap_tx.clone();
+    priority.connect_changed(move |priority| {
+        // Showing a task picks its priority too, which isn't a change
+        let lb: ListBox = priority_ui.get_object("content").unwrap();
+        let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+        let id = priority.get_active_id().unwrap_or_default();
+        let current = uid.and_then(|uid| {
+            priority_tasks.borrow().get(&uid).map(|t| t.priority())
+        });
+        match (uid, Priority::from_id(&id), current) {
+            (Some(uid), Some(chosen), Some(current)) if chosen != current => {
+                let msg = Message::SetPriority(uid, chosen);
+                let _ = priority_poller.send(msg);
+            }
+            _ => (),
+        }
+    });
+
+    let replies: ToggleButton = builder.get_object("detail_replies").unwrap();
+    let replies_ui = builder.clone();
+    let replies_tasks = tasks.clone();
+    let replies_filter = filter.clone();
+    replies.connect_toggled(move |replies| {
+        let lb: ListBox = replies_ui.get_object("content").unwrap();
+        let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+        let root = uid.and_then(|uid| {
+            let tasks = replies_tasks.borrow();
+            let root = tasks.get(&uid).map(|t| threads::root(&tasks, t).uid);
+            root
+        });
+        // Showing a task sets it as it is, which is no change
+        let expanded = replies.get_active();
+        match root {
+            Some(root)
+                if replies_filter.borrow().expanded.contains(&root)
+                    != expanded =>
+            {
+                GLOBAL.with(|global| {
+                    if let Some(ref ui) = *global.borrow() {
+                        expand(ui, root, expanded);
+                    }
+                });
+            }
+            _ => (),
+        }
+    });
+
+    let tag_entry: Entry = builder.get_object("tag_entry").unwrap();
+    let tag_ui = builder.clone();
+    let tag_poller = imap_tx.clone();
+    tag_entry.connect_activate(move |entry| {
+        let text = entry.get_text().unwrap_or_default();
+        let tag = text.trim().trim_left_matches('#').to_string();
+        let lb: ListBox = tag_ui.get_object("content").unwrap();
+        let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+        if let (Some(uid), false) = (uid, tag.is_empty()) {
+            let _ = tag_poller.send(Message::AddTag(uid, tag));
+        }
+        entry.set_text("");
+    });
+
+    lb.connect_row_activated(|_, _| open_selected());
+    let open: Button = builder.get_object("open").unwrap();
+    open.connect_clicked(|_| open_selected());
+
+    let view = Rc::new(RefCell::new(view::load()));
+    setup_view(&builder, &tasks, &view);
+    setup_search(&builder, &filter, &tasks);
+
+    let tray = Tray::new(tray_ui.clone(), imap_tx.clone());
+    let ui = Ui {
+        builder: builder,
+        poller: imap_tx.clone(),
+        tx: tray_ui,
+        rx: ui_rx,
+        tray: tray,
+        stoppers: stoppers,
+        tasks: tasks,
+        view: view,
+        filter: filter,
+        pending: Vec::new(),
+        batch: 0,
+        done: Vec::new(),
+        copies: HashMap::new(),
+        grace: grace,
+        shortcuts: shortcuts::load(),
+        sync: SyncStatus::default(),
+        reminders: Scheduler::new(SystemClock, leads, nag),
+        notifiers: notifiers,
+        quiet: quiet,
+        dnd: quiet::watch_dnd(),
+        changes: Changes::default(),
+        own: Own::default(),
+        completed: HashSet::new(),
+        editing: editing,
+        folder: folder.clone(),
+        mail_client: mail_client,
+    };
+    GLOBAL.with(move |global| *global.borrow_mut() = Some(ui));
     glib::timeout_add(100, receive);
+    glib::timeout_add_seconds(REMIND_CHECK, remind);
 
+    let control_poller = imap_tx.clone();
+    threads.push(
+        thread::Builder::new()
+            .name("control".to_string())
+            .spawn(move || match control::socket_path() {
+                Ok(socket) => control::start(
+                    &socket,
+                    control_poller,
+                    control_ui,
+                    control_rx,
+                ),
+                Err(e) => error!("Couldn't place the control socket: {}", e),
+            })
+            .unwrap(),
+    );
+
+    let backup_poller = imap_tx.clone();
     let creds = parser::get_credentials(conf).unwrap();
-    let poller_thread = thread::Builder::new()
-        .name("poller".to_string())
-        .spawn(move || {
-            poller::start(creds, &folder, ui_tx, imap_tx, imap_rx);
-        })
-        .unwrap();
+    threads.push(
+        thread::Builder::new()
+            .name("poller".to_string())
+            .spawn(move || {
+                poller::start(
+                    creds,
+                    &folder,
+                    &done,
+                    listeners,
+                    imap_tx,
+                    imap_rx,
+                );
+            })
+            .unwrap(),
+    );
 
-    let backup_thread = thread::Builder::new()
-        .name("backup".to_string())
-        .spawn(move || {
-            backup::start(backup_tx, backup_rx);
-        })
-        .unwrap();
+    threads.push(
+        thread::Builder::new()
+            .name("backup".to_string())
+            .spawn(move || {
+                backup::start(backup_tx, backup_rx, backup_poller, digest);
+            })
+            .unwrap(),
+    );
 
     gtk::main();
     info!("Waiting for all threads to finish");
-    let _ = poller_thread.join();
-    let _ = backup_thread.join();
+    for t in threads {
+        let _ = t.join();
+    }
 }
 
 fn receive() -> glib::Continue {
     GLOBAL.with(|global| {
-        if let Some((ref ui, ref tx, ref rx)) = *global.borrow_mut() {
-            while let Ok(msg) = rx.try_recv() {
+        if let Some(ref mut ui) = *global.borrow_mut() {
+            while let Ok(msg) = ui.rx.try_recv() {
+                let builder = &ui.builder;
                 match msg {
-                    Message::Tasks(ref tasks) => update_list(ui, tasks, tx),
-                    Message::Connected => update_status(ui, "Connected", true),
+                    Message::Tasks(tasks) => {
+                        let new: HashMap<u64, Task> =
+                            tasks.iter().map(|t| (t.uid, t.clone())).collect();
+                        // Searches on the server only know about the
+                        // messages that were there back then
+                        let changed = {
+                            let old = ui.tasks.borrow();
+                            new.len() != old.len()
+                                || new.keys().any(|uid| !old.contains_key(uid))
+                        };
+                        if changed {
+                            ui.filter.borrow_mut().found.clear();
+                        }
+                        {
+                            let mut filter = ui.filter.borrow_mut();
+                            filter
+                                .completed
+                                .retain(|uid| new.contains_key(uid));
+                            filter
+                                .bodies
+                                .retain(|uid, _| new.contains_key(uid));
+                            let folder = &ui.folder;
+                            for &uid in new.keys() {
+                                filter.bodies.entry(uid).or_insert_with(|| {
+                                    cache::load(folder, uid).map(|d| d.body)
+                                });
+                            }
+                        }
+                        let removed: Vec<Task> = ui.tasks
+                            .borrow()
+                            .values()
+                            .filter(|t| !new.contains_key(&t.uid))
+                            .cloned()
+                            .collect();
+                        *ui.tasks.borrow_mut() = new;
+                        let added = update_list(builder, &tasks);
+                        show_flags(
+                            builder,
+                            &ui.tasks.borrow(),
+                            &ui.filter.borrow(),
+                            &ui.poller,
+                        );
+                        announce(ui, added, removed, tasks.len());
+                        ui.own.synced();
+                        apply_search(ui);
+                        ui.tray.set_tasks(&tasks);
+                        ui.sync = SyncStatus {
+                            connected: true,
+                            syncing: false,
+                            last: Some(Local::now()),
+                            error: None,
+                        };
+                        update_status(ui);
+                    }
+                    Message::Syncing => {
+                        ui.sync.syncing = true;
+                        update_status(ui);
+                    }
+                    Message::SyncError(e) => {
+                        ui.sync.syncing = false;
+                        ui.sync.error = Some(e);
+                        update_status(ui);
+                    }
+                    Message::Found(found) => {
+                        ui.filter.borrow_mut().found.extend(found);
+                        apply_search(ui);
+                    }
+                    Message::Connected => {
+                        ui.sync.connected = true;
+                        update_status(ui);
+                    }
                     Message::NotConnected => {
-                        update_status(ui, "Connecting...", false)
+                        ui.sync.connected = false;
+                        update_status(ui);
+                    }
+                    Message::Present => {
+                        let window: Window =
+                            builder.get_object("window").unwrap();
+                        window.present();
+                    }
+                    Message::Toggle => {
+                        let window: Window =
+                            builder.get_object("window").unwrap();
+                        window.set_visible(!window.is_visible());
+                    }
+                    Message::AskTask => ask_task(ui, None),
+                    Message::Complete(uid) => complete_later(ui, vec![uid]),
+                    Message::Completed(uid) => {
+                        ui.completed.insert(uid);
+                        if let Some(task) = ui.tasks.borrow().get(&uid) {
+                            if let Err(e) = digest::record(&task.title) {
+                                error!("Couldn't log the completion: {}", e);
+                            }
+                        }
+                    }
+                    Message::CompleteFailed(uid, e) => {
+                        complete_failed(ui, uid, &e)
+                    }
+                    Message::Replaced(uid, id) => {
+                        ui.own.remove(uid);
+                        ui.own.add(&id);
+                        // Undoing the completion of a recurring task takes
+                        // back the copy it got
+                        if ui.done.iter().any(|t| t.uid == uid) {
+                            ui.copies.insert(uid, id);
+                        }
+                    }
+                    Message::Snooze(uid) => ui
+                        .reminders
+                        .snooze(uid, Duration::minutes(mail_todo::SNOOZE)),
+                    Message::Open(uid) => {
+                        // Replies only show in expanded threads
+                        let root = ui.tasks
+                            .borrow()
+                            .get(&uid)
+                            .and_then(|t| t.parent);
+                        if let Some(root) = root {
+                            expand(ui, root, true);
+                        }
+                        select_task(builder, uid)
+                    }
+                    Message::Saved(ref path) => {
+                        let msg = format!("Saved {}", path.display());
+                        push_status(builder, &msg);
+                    }
+                    Message::Details(ref details) => {
+                        let body = Some(details.body.clone());
+                        ui.filter.borrow_mut().bodies.insert(details.uid, body);
+                        show_details(builder, details, &ui.poller)
                     }
+                    Message::TrayFallback => {
+                        ui.tray.fallback(ui.tx.clone(), ui.poller.clone())
+                    }
+                    Message::Quit => quit(ui),
                     m => panic!("Main thread got unexpected message! {:?}", m),
                 }
             }
@@ -143,98 +967,1129 @@ fn receive() -> glib::Continue {
     glib::Continue(true)
 }
 
-fn update_list(ui: &Builder, tasks: &HashSet<Task>, tx: &Sender<Message>) {
-    let lb: ListBox = ui.get_object("content").unwrap();
-    let mut notify = false;
+// Shows a notification for every task whose reminder is due, leaving out
+// the ones just completed here, and what was held back during quiet hours
+// once they're over
+fn remind() -> glib::Continue {
+    GLOBAL.with(|global| {
+        if let Some(ref mut ui) = *global.borrow_mut() {
+            let notes: Vec<Note> = {
+                let tasks = ui.tasks.borrow();
+                let filter = ui.filter.borrow();
+                let reminders = ui.reminders.check(
+                    tasks
+                        .values()
+                        .filter(|t| !filter.completed.contains(&t.uid)),
+                );
+                reminders
+                    .into_iter()
+                    .map(|r| Note {
+                        kind: Kind::Reminder,
+                        summary: r.title,
+                        body: r.text,
+                        task: tasks.get(&r.uid).cloned(),
+                    })
+                    .collect()
+            };
+            for note in notes {
+                deliver(ui, note);
+            }
+
+            if ui.quiet.is_holding() {
+                let now = Local::now();
+                let dnd = ui.dnd.load(Ordering::Relaxed);
+                if let Some(digest) = ui.quiet.release(now, dnd) {
+                    notifier::notify_all(&ui.notifiers, &digest, dnd);
+                }
+                if let Some(digest) = ui.quiet.release_desktop(dnd) {
+                    notifier::notify_desktop(&ui.notifiers, &digest);
+                }
+            }
+        }
+    });
+    glib::Continue(true)
+}
 
-    // "titles" will serve to keep track of what's in the UI and what's not
-    let mut titles: HashSet<&str> = HashSet::new();
-    for t in tasks.iter() {
-        titles.insert(&t.title);
+// Sends the note to the notifiers, or holds it if it's quiet now
+fn deliver(ui: &mut Ui, note: Note) {
+    let dnd = ui.dnd.load(Ordering::Relaxed);
+    if let Some(note) = ui.quiet.pass(note, Local::now(), dnd) {
+        notifier::notify_all(&ui.notifiers, &note, dnd);
     }
+}
+
+fn quit(ui: &mut Ui) {
+    info!("Closing...");
+    // The poller gets these before the Quit
+    commit_completions(ui);
+    for s in ui.stoppers.iter() {
+        let _ = s.send(Message::Quit);
+    }
+    gtk::main_quit();
+}
+
+// Returns the tasks that weren't in the list
+fn update_list<'a>(ui: &Builder, tasks: &'a HashSet<Task>) -> Vec<&'a Task> {
+    let lb: ListBox = ui.get_object("content").unwrap();
+
+    // "missing" will serve to keep track of what's in the UI and what's not
+    let mut missing: HashMap<u64, &Task> =
+        tasks.iter().map(|t| (t.uid, t)).collect();
+    let replies = threads::replies(tasks);
+    let replies = |task: &Task| replies.get(&task.uid).cloned().unwrap_or(0);
 
     // loop over the UI rows to see what needs to be deleted, and delete it
     for wrow in lb.get_children() {
         let row: ListBoxRow = wrow.downcast().unwrap();
-        let wcheck = row.get_child().unwrap();
-        let check: CheckButton = wcheck.downcast().unwrap();
-        let label = check.get_label().unwrap();
-
-        if !titles.contains::<str>(&label) {
-            // If the row is not in the titles, needs to be deleted
-            notify = true;
-            row.destroy();
-        } else {
-            // If the row is in the titles, delete from titles so it's not
-            // added again
-            titles.remove::<str>(&label);
+        match row_uid(&row).and_then(|uid| missing.remove(&uid)) {
+            // Still there, but its subject or flags may have changed
+            Some(task) => {
+                let check = row.get_child().unwrap();
+                fill_row(&check.downcast().unwrap(), task, replies(task));
+            }
+            None => row.destroy(),
         }
     }
 
     // add whatever task is missing to the interface
-    for task in tasks.iter() {
-        // If the task is not in "titles", means we've already seen it in
-        // the interface
-        if !titles.contains::<str>(&task.title) {
+    for task in missing.values() {
+        let check = CheckButton::new();
+        check.set_name(&task.uid.to_string());
+        let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        let mark = Label::new(None);
+        mark.set_valign(gtk::Align::Start);
+        row.pack_start(&mark, false, false, 0);
+        let labels = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let title = Label::new(None);
+        title.set_halign(gtk::Align::Start);
+        labels.pack_start(&title, false, false, 0);
+        let info = Label::new(None);
+        info.set_halign(gtk::Align::Start);
+        if let Some(style) = info.get_style_context() {
+            style.add_class("dim-label");
+        }
+        labels.pack_start(&info, false, false, 0);
+        let chips = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+        labels.pack_start(&chips, false, false, 2);
+        row.pack_start(&labels, true, true, 0);
+        check.add(&row);
+        fill_row(&check, task, replies(task));
+        lb.add(&check);
+    }
+
+    lb.show_all();
+    missing.values().cloned().collect()
+}
+
+// On the first sync, how many tasks there are. After that, the tasks
+// added, completed or removed by something else than this window, gathered
+// for a moment so a burst of them makes a single notification.
+fn announce(ui: &mut Ui, added: Vec<&Task>, removed: Vec<Task>, total: usize) {
+    if ui.sync.last.is_none() {
+        let note = Note {
+            kind: Kind::Pending,
+            summary: format!("{} tasks pending", total),
+            body: String::new(),
+            task: None,
+        };
+        deliver(ui, note);
+        return;
+    }
+
+    let was_empty = ui.changes.is_empty();
+    for task in added {
+        if ui.own.added.remove(&task.message_id).is_none() {
+            ui.changes.added.push(task.clone());
+        }
+    }
+    for task in removed {
+        let completed = ui.completed.remove(&task.uid);
+        if ui.own.removed.remove(&task.uid).is_some() {
             continue;
         }
+        if completed {
+            ui.changes.completed.push(task);
+        } else {
+            ui.changes.removed.push(task);
+        }
+    }
 
-        notify = true;
-        let check = CheckButton::new_with_label(&task.title);
-        lb.add(&check);
+    if was_empty && !ui.changes.is_empty() {
+        glib::timeout_add_seconds(COALESCE, || {
+            GLOBAL.with(|global| {
+                if let Some(ref mut ui) = *global.borrow_mut() {
+                    let changes = std::mem::replace(
+                        &mut ui.changes,
+                        Changes::default(),
+                    );
+                    if let Some(note) = changes.note() {
+                        deliver(ui, note);
+                    }
+                }
+            });
+            glib::Continue(false)
+        });
+    }
+}
+
+// Sets the priority mark, the title, the "sender · date · size · due ·
+// recurrence · replies" line, which turns red once the task is overdue,
+// and the tags of a row's check. Replies are indented under the task they
+// belong to.
+fn fill_row(check: &CheckButton, task: &Task, replies: usize) {
+    check.set_margin_start(if task.parent.is_some() { 24 } else { 0 });
+    let row: gtk::Box = check.get_child().unwrap().downcast().unwrap();
+    let children = row.get_children();
+    let mark: Label = children[0].clone().downcast().unwrap();
+    let labels: gtk::Box = children[1].clone().downcast().unwrap();
+    let chips: gtk::Box = labels
+        .get_children()
+        .into_iter()
+        .filter_map(|c| c.downcast().ok())
+        .next()
+        .unwrap();
+    let labels: Vec<Label> = labels
+        .get_children()
+        .into_iter()
+        .filter_map(|l| l.downcast().ok())
+        .collect();
+    labels[0].set_text(&task.title);
+
+    for chip in chips.get_children() {
+        chip.destroy();
+    }
+    for tag in &task.tags {
+        let chip = Label::new(Some(&tag[..]));
+        if let Some(style) = chip.get_style_context() {
+            style.add_class("tag");
+        }
+        chips.pack_start(&chip, false, false, 0);
+    }
+    chips.show_all();
+
+    let mut info = Vec::new();
+    if !task.sender().is_empty() {
+        info.push(task.sender().to_string());
+    }
+    if let Some(date) = task.date {
+        let date = date.with_timezone(&Local);
+        info.push(date.format("%a %e %b %Y, %H:%M").to_string());
+    }
+    if task.size > 0 {
+        info.push(details::human_size(task.size as usize));
+    }
+    let now = Local::now();
+    if let Some(date) = task.due {
+        info.push(due::describe(date, now));
+    }
+    if let Some(rule) = task.recurrence() {
+        info.push(rule.describe());
+    }
+    match replies {
+        0 => (),
+        1 => info.push(String::from("1 reply")),
+        n => info.push(format!("{} replies", n)),
+    }
+    labels[1].set_text(&info.join(" · "));
+    if let Some(style) = labels[1].get_style_context() {
+        if due::is_overdue(task.due, now) {
+            style.add_class("error");
+        } else {
+            style.remove_class("error");
+        }
+    }
+
+    // "!!!" in red for a high priority, "!!" in orange and "!" in grey
+    let (text, class) = match task.priority() {
+        Priority::High => ("!!!", "error"),
+        Priority::Medium => ("!!", "warning"),
+        Priority::Low => ("!", "dim-label"),
+        Priority::None => ("", ""),
+    };
+    mark.set_text(text);
+    if let Some(style) = mark.get_style_context() {
+        for old in &["error", "warning", "dim-label"] {
+            style.remove_class(old);
+        }
+        if !class.is_empty() {
+            style.add_class(class);
+        }
+    }
+}
+
+// The priority, tags and replies of the selected task in the detail pane
+fn show_flags(
+    ui: &Builder,
+    tasks: &HashMap<u64, Task>,
+    filter: &Filter,
+    poller: &Sender<Message>,
+) {
+    let lb: ListBox = ui.get_object("content").unwrap();
+    let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+    let task = match uid.and_then(|uid| tasks.get(&uid)) {
+        Some(task) => task,
+        None => return,
+    };
+    let priority: ComboBoxText = ui.get_object("detail_priority").unwrap();
+    priority.set_active_id(Some(task.priority().id()));
+
+    let chips: gtk::Box = ui.get_object("detail_tags").unwrap();
+    for chip in chips.get_children() {
+        chip.destroy();
+    }
+    for tag in &task.tags {
+        chips.pack_start(&tag_chip(ui, task.uid, tag, poller), false, false, 0);
+    }
+    chips.show_all();
+
+    // Those of the thread, for a reply
+    let root = threads::root(tasks, task).uid;
+    let count = threads::replies(tasks.values())
+        .get(&root)
+        .cloned()
+        .unwrap_or(0);
+    let replies: ToggleButton = ui.get_object("detail_replies").unwrap();
+    replies.set_visible(count > 0);
+    replies.set_label(&match count {
+        1 => String::from("1 reply"),
+        n => format!("{} replies", n),
+    });
+    replies.set_active(filter.expanded.contains(&root));
+}
 
-        // copy here the uid so the closure does not reference the task
-        let uid = task.uid;
-        let tx = tx.clone();
-        debug!("Storing uid {} in destroy closure", uid);
-        check.connect_destroy(move |_| {
-            if let Err(e) = tx.send(Message::Delete(uid)) {
-                error!("Couldn't send delete message {}: {}", uid, e);
+// Shows or hides the replies of a thread in the list. Hiding them selects
+// the root, so the selection isn't left on a hidden row.
+fn expand(ui: &Ui, root: u64, expanded: bool) {
+    let changed = {
+        let mut filter = ui.filter.borrow_mut();
+        if expanded {
+            filter.expanded.insert(root)
+        } else {
+            filter.expanded.remove(&root)
+        }
+    };
+    if !changed {
+        return;
+    }
+    refilter(ui);
+
+    let lb: ListBox = ui.builder.get_object("content").unwrap();
+    let selected = lb.get_selected_row().and_then(|r| row_uid(&r));
+    let tasks = ui.tasks.borrow();
+    let in_thread = selected
+        .and_then(|uid| tasks.get(&uid))
+        .map_or(false, |t| threads::root(&tasks, t).uid == root);
+    if !in_thread {
+        return;
+    }
+    if !expanded && selected != Some(root) {
+        select_task(&ui.builder, root);
+    }
+    let replies: ToggleButton =
+        ui.builder.get_object("detail_replies").unwrap();
+    replies.set_active(expanded);
+}
+
+// A tag in the detail pane, which shows the tasks with it when clicked,
+// and a button to remove it
+fn tag_chip(
+    ui: &Builder,
+    uid: u64,
+    tag: &str,
+    poller: &Sender<Message>,
+) -> gtk::Box {
+    let chip = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    if let Some(style) = chip.get_style_context() {
+        style.add_class("tag");
+    }
+
+    let show = Button::new_with_label(tag);
+    show.set_relief(gtk::ReliefStyle::None);
+    show.set_tooltip_text(Some("Show the tasks with this tag"));
+    let query = if tag.contains(char::is_whitespace) {
+        format!("tag:\"{}\"", tag)
+    } else {
+        format!("tag:{}", tag)
+    };
+    let ui = ui.clone();
+    show.connect_clicked(move |_| {
+        let toggle: ToggleButton = ui.get_object("search_toggle").unwrap();
+        let entry: SearchEntry = ui.get_object("search_entry").unwrap();
+        toggle.set_active(true);
+        entry.set_text(&query);
+    });
+    chip.pack_start(&show, false, false, 0);
+
+    let remove = Button::new_with_label("×");
+    remove.set_relief(gtk::ReliefStyle::None);
+    remove.set_tooltip_text(Some("Remove the tag"));
+    let tx = poller.clone();
+    let tag = tag.to_string();
+    remove.connect_clicked(move |_| {
+        let _ = tx.send(Message::RemoveTag(uid, tag.clone()));
+    });
+    chip.pack_start(&remove, false, false, 0);
+    chip
+}
+
+// Hooks the sort and group combo boxes and the manual ordering buttons to
+// the view, and the view to the order of the list
+fn setup_view(
+    ui: &Builder,
+    tasks: &Rc<RefCell<HashMap<u64, Task>>>,
+    view: &Rc<RefCell<View>>,
+) {
+    let lb: ListBox = ui.get_object("content").unwrap();
+    let list_tasks = tasks.clone();
+    let list_view = view.clone();
+    lb.set_sort_func(Some(Box::new(move |a: &ListBoxRow, b: &ListBoxRow| {
+        let tasks = list_tasks.borrow();
+        match (
+            row_uid(a).and_then(|uid| tasks.get(&uid)),
+            row_uid(b).and_then(|uid| tasks.get(&uid)),
+        ) {
+            (Some(a), Some(b)) => {
+                view::compare_threads(&list_view.borrow(), &tasks, a, b) as i32
             }
+            _ => 0,
+        }
+    })));
+
+    // A header goes above the first row of each group, which GTK keeps up
+    // to date as rows are added, sorted or filtered. The row before is the
+    // previous one still shown, found by index as there's none for the
+    // first row.
+    let header_tasks = tasks.clone();
+    let header_view = view.clone();
+    lb.set_header_func(Some(Box::new(move |row: &ListBoxRow, _: &ListBoxRow| {
+        let tasks = header_tasks.borrow();
+        let view = header_view.borrow();
+        let task = |row: &ListBoxRow| row_uid(row).and_then(|u| tasks.get(&u));
+        let lb: Option<ListBox> =
+            row.get_parent().and_then(|p| p.downcast().ok());
+        let before = lb.and_then(|lb| {
+            (0..row.get_index())
+                .rev()
+                .filter_map(|i| lb.get_row_at_index(i))
+                .find(|r| r.get_child_visible())
+        });
+
+        let text = task(row).and_then(|t| {
+            let before = before.as_ref().and_then(|b| task(b));
+            view::header_above(view.group, &tasks, t, before)
+        });
+        let text = match text {
+            Some(text) => text,
+            None => {
+                row.set_header(None::<&Label>);
+                return;
+            }
+        };
+        let label = Label::new(Some(&text[..]));
+        label.set_halign(gtk::Align::Start);
+        label.set_margin_top(6);
+        if let Some(style) = label.get_style_context() {
+            style.add_class("dim-label");
+        }
+        label.show();
+        row.set_header(Some(&label));
+    })));
+
+    let sort: ComboBoxText = ui.get_object("sort").unwrap();
+    let group: ComboBoxText = ui.get_object("group").unwrap();
+    sort.set_active_id(Some(view.borrow().sort.id()));
+    group.set_active_id(Some(view.borrow().group.id()));
+    show_move_buttons(ui, view.borrow().sort);
+
+    let sort_ui = ui.clone();
+    let sort_view = view.clone();
+    sort.connect_changed(move |sort| {
+        let id = sort.get_active_id().unwrap_or_default();
+        if let Some(sort) = Sort::from_id(&id) {
+            sort_view.borrow_mut().sort = sort;
+            show_move_buttons(&sort_ui, sort);
+            save_view(&sort_ui, &sort_view.borrow());
+        }
+    });
+    let group_ui = ui.clone();
+    let group_view = view.clone();
+    group.connect_changed(move |group| {
+        let id = group.get_active_id().unwrap_or_default();
+        if let Some(group) = Group::from_id(&id) {
+            group_view.borrow_mut().group = group;
+            save_view(&group_ui, &group_view.borrow());
+        }
+    });
+
+    for &(name, offset) in [("move_up", -1), ("move_down", 1)].iter() {
+        let button: Button = ui.get_object(name).unwrap();
+        let (builder, tasks, view) = (ui.clone(), tasks.clone(), view.clone());
+        button.connect_clicked(move |_| {
+            move_selected(&builder, &tasks.borrow(), &view, offset)
         });
     }
+}
 
-    lb.show_all();
+fn show_move_buttons(ui: &Builder, sort: Sort) {
+    for name in ["move_up", "move_down"].iter() {
+        let button: Button = ui.get_object(name).unwrap();
+        button.set_visible(sort == Sort::Manual);
+    }
+}
 
-    if notify {
-        notifier::notify(
-            &format!("{} tasks pending", tasks.len()),
-            mail_todo::ICON,
-            mail_todo::NOTIF_TIMEOUT,
-        );
+fn save_view(ui: &Builder, view: &View) {
+    if let Err(e) = view::save(view) {
+        warn!("Couldn't save the view settings: {}", e);
+    }
+    arrange(ui);
+}
+
+// Swaps the selected task with the one offset rows away, which turns the
+// current order into the manual one
+fn move_selected(
+    ui: &Builder,
+    tasks: &HashMap<u64, Task>,
+    view: &Rc<RefCell<View>>,
+    offset: i32,
+) {
+    // Threads move as a whole, by their root
+    let lb: ListBox = ui.get_object("content").unwrap();
+    let selected = match lb.get_selected_row()
+        .and_then(|row| row_uid(&row))
+        .and_then(|uid| tasks.get(&uid))
+    {
+        Some(task) => threads::root(tasks, task).uid,
+        None => return,
+    };
+    let roots: Vec<&Task> = lb.get_children()
+        .into_iter()
+        .filter_map(|w| w.downcast::<ListBoxRow>().ok())
+        .filter_map(|row| row_uid(&row).and_then(|uid| tasks.get(&uid)))
+        .filter(|task| task.parent.is_none())
+        .collect();
+    let index = match roots.iter().position(|t| t.uid == selected) {
+        Some(index) => index as i32,
+        None => return,
+    };
+    let mut keys: Vec<String> =
+        roots.into_iter().map(view::manual_key).collect();
+    let other = index + offset;
+    if other < 0 || other as usize >= keys.len() {
+        return;
+    }
+    keys.swap(index as usize, other as usize);
+
+    view.borrow_mut().manual = keys;
+    save_view(ui, &view.borrow());
+}
+
+// Sorts the rows again, and has GTK work out the headers of the groups
+fn arrange(ui: &Builder) {
+    let lb: ListBox = ui.get_object("content").unwrap();
+    lb.invalidate_sort();
+    lb.invalidate_headers();
+}
+
+fn setup_search(
+    ui: &Builder,
+    filter: &Rc<RefCell<Filter>>,
+    tasks: &Rc<RefCell<HashMap<u64, Task>>>,
+) {
+    let lb: ListBox = ui.get_object("content").unwrap();
+    let filter = filter.clone();
+    let tasks = tasks.clone();
+    lb.set_filter_func(Some(Box::new(move |row: &ListBoxRow| {
+        let filter = filter.borrow();
+        let uid = match row_uid(row) {
+            Some(uid) => uid,
+            None => return true,
+        };
+        // Searches show the replies that match on their own
+        let parent = tasks.borrow().get(&uid).and_then(|t| t.parent);
+        let folded = filter.visible.is_none()
+            && parent.map_or(false, |p| !filter.expanded.contains(&p));
+        !filter.completed.contains(&uid) && !folded
+            && filter.visible.as_ref().map_or(true, |v| v.contains(&uid))
+    })));
+
+    let bar: SearchBar = ui.get_object("search_bar").unwrap();
+    let entry: SearchEntry = ui.get_object("search_entry").unwrap();
+    let toggle: ToggleButton = ui.get_object("search_toggle").unwrap();
+    bar.connect_entry(&entry);
+
+    let toggle_bar = bar.clone();
+    let toggle_entry = entry.clone();
+    toggle.connect_toggled(move |toggle| {
+        toggle_bar.set_search_mode(toggle.get_active());
+        if toggle.get_active() {
+            toggle_entry.grab_focus();
+        } else {
+            toggle_entry.set_text("");
+        }
+    });
+    // Escape and the close button hide the bar without the toggle
+    bar.connect_property_search_mode_enabled_notify(move |bar| {
+        toggle.set_active(bar.get_search_mode());
+    });
+
+    entry.connect_search_changed(|entry| {
+        let text = entry.get_text().unwrap_or_default();
+        GLOBAL.with(|global| {
+            if let Some(ref ui) = *global.borrow() {
+                ui.filter.borrow_mut().query = search::parse(&text);
+                apply_search(ui);
+            }
+        });
+    });
+}
+
+// Works out which tasks match the search. Bodies not in the cache can only
+// be searched on the server, which answers with a Message::Found.
+fn apply_search(ui: &Ui) {
+    {
+        let tasks = ui.tasks.borrow();
+        let mut search = ui.filter.borrow_mut();
+        if search.query.is_empty() {
+            search.visible = None;
+        } else {
+            let uncached = tasks.keys().any(|uid| {
+                search.bodies.get(uid).map_or(true, |b| b.is_none())
+            });
+            let terms: Vec<String> = search
+                .query
+                .body_terms()
+                .into_iter()
+                .filter(|t| uncached && !search.found.contains_key(t))
+                .collect();
+            if !terms.is_empty() {
+                // No results yet, but they're on their way
+                for term in terms.iter() {
+                    search.found.insert(term.clone(), HashSet::new());
+                }
+                let _ = ui.poller.send(Message::Search(terms));
+            }
+
+            let visible = {
+                let bodies = &search.bodies;
+                let body = |uid| bodies.get(&uid).and_then(|b| b.clone());
+                tasks
+                    .values()
+                    .filter(|t| search.query.matches(t, &body, &search.found))
+                    .map(|t| t.uid)
+                    .collect()
+            };
+            search.visible = Some(visible);
+        }
+    }
+
+    refilter(ui);
+}
+
+fn refilter(ui: &Ui) {
+    let lb: ListBox = ui.builder.get_object("content").unwrap();
+    lb.invalidate_filter();
+    arrange(&ui.builder);
+}
+
+fn run_action(ui: &mut Ui, action: Action) {
+    let window: Window = ui.builder.get_object("window").unwrap();
+    let lb: ListBox = ui.builder.get_object("content").unwrap();
+    match action {
+        Action::Next => select_next(&lb, 1),
+        Action::Previous => select_next(&lb, -1),
+        Action::Toggle => if let Some(row) = lb.get_selected_row() {
+            let check: CheckButton =
+                row.get_child().unwrap().downcast().unwrap();
+            check.set_active(!check.get_active());
+        },
+        Action::Complete => complete_checked(ui),
+        Action::Add => ask_task(ui, None),
+        Action::Edit => {
+            let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+            let task = uid.and_then(|uid| ui.tasks.borrow().get(&uid).cloned());
+            if task.is_some() {
+                ask_task(ui, task.as_ref());
+            }
+        }
+        Action::Due => {
+            let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+            let task = uid.and_then(|uid| ui.tasks.borrow().get(&uid).cloned());
+            if let Some(task) = task {
+                ask_due(ui, &task);
+            }
+        }
+        Action::Priority => {
+            let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+            let priority = uid.and_then(|uid| {
+                ui.tasks.borrow().get(&uid).map(|t| t.priority())
+            });
+            if let (Some(uid), Some(priority)) = (uid, priority) {
+                let msg = Message::SetPriority(uid, priority.next());
+                let _ = ui.poller.send(msg);
+            }
+        }
+        Action::Expand | Action::Collapse => {
+            let uid = lb.get_selected_row().and_then(|r| row_uid(&r));
+            let root = uid.and_then(|uid| {
+                let tasks = ui.tasks.borrow();
+                let root =
+                    tasks.get(&uid).map(|t| threads::root(&tasks, t).uid);
+                root
+            });
+            if let Some(root) = root {
+                expand(ui, root, action == Action::Expand);
+            }
+        }
+        Action::Tag => if lb.get_selected_row().is_some() {
+            let entry: Entry = ui.builder.get_object("tag_entry").unwrap();
+            entry.grab_focus();
+        },
+        Action::Refresh => {
+            let _ = ui.poller.send(Message::Refresh);
+        }
+        Action::Search => {
+            // Closing the search clears it, and that reaches GLOBAL right
+            // away, so it has to wait until we're done with it
+            let toggle: ToggleButton =
+                ui.builder.get_object("search_toggle").unwrap();
+            glib::idle_add(move || {
+                toggle.set_active(!toggle.get_active());
+                glib::Continue(false)
+            });
+        }
+        Action::Undo => undo(ui),
+        Action::Hide => window.hide(),
+        Action::Shortcuts => {
+            shortcuts::window(&ui.shortcuts, &window).show_all()
+        }
+    }
+}
+
+// Brings up the window with the task selected and its details shown
+fn select_task(ui: &Builder, uid: u64) {
+    let window: Window = ui.get_object("window").unwrap();
+    let lb: ListBox = ui.get_object("content").unwrap();
+    window.present();
+    let row = lb.get_children()
+        .into_iter()
+        .filter_map(|w| w.downcast::<ListBoxRow>().ok())
+        .find(|r| row_uid(r) == Some(uid));
+    match row {
+        Some(row) => {
+            lb.select_row(Some(&row));
+            row.grab_focus();
+        }
+        None => warn!("Task {} isn't in the list anymore", uid),
+    }
+}
+
+// Moves the selection to the next row shown in that direction
+fn select_next(lb: &ListBox, offset: i32) {
+    let rows: Vec<ListBoxRow> = lb.get_children()
+        .into_iter()
+        .filter_map(|w| w.downcast::<ListBoxRow>().ok())
+        .filter(|r| r.get_child_visible())
+        .collect();
+    if rows.is_empty() {
+        return;
+    }
+    let selected = lb.get_selected_row();
+    let index = match rows.iter().position(|r| Some(r) == selected.as_ref()) {
+        Some(i) => (i as i32 + offset).max(0).min(rows.len() as i32 - 1),
+        None if offset > 0 => 0,
+        None => rows.len() as i32 - 1,
+    };
+    let row = &rows[index as usize];
+    lb.select_row(Some(row));
+    row.grab_focus();
+}
+
+// The add dialog, filled with the title of the task when editing one
+fn ask_task(ui: &Ui, task: Option<&Task>) {
+    let dialog: Dialog = ui.builder.get_object("add_dialog").unwrap();
+    let entry: Entry = ui.builder.get_object("add_entry").unwrap();
+    let ok: Button = ui.builder.get_object("add_ok").unwrap();
+    ui.editing.set(task.map(|t| t.uid));
+    match task {
+        Some(task) => {
+            dialog.set_title("Edit task");
+            ok.set_label("gtk-save");
+            entry.set_text(&task.title);
+        }
+        None => {
+            dialog.set_title("Add task");
+            ok.set_label("gtk-add");
+        }
+    }
+    dialog.show_all();
+    dialog.present();
+}
+
+// The due date dialog, showing the current one or else today
+fn ask_due(ui: &Ui, task: &Task) {
+    let dialog: Dialog = ui.builder.get_object("due_dialog").unwrap();
+    let calendar: Calendar = ui.builder.get_object("due_calendar").unwrap();
+    let time: Entry = ui.builder.get_object("due_time").unwrap();
+    let date = task.due
+        .map(|d| d.with_timezone(&Local))
+        .unwrap_or_else(Local::now);
+    calendar.select_month(date.month0(), date.year() as u32);
+    calendar.select_day(date.day());
+    match task.due {
+        Some(_) if date.hour() != mail_todo::DUE_HOUR || date.minute() != 0 => {
+            time.set_text(&date.format("%H:%M").to_string())
+        }
+        _ => time.set_text(""),
+    }
+    dialog.show_all();
+    dialog.present();
+}
+
+// The date picked in the due date dialog, None if the time isn't valid
+fn chosen_due(ui: &Builder) -> Option<DateTime<FixedOffset>> {
+    let calendar: Calendar = ui.get_object("due_calendar").unwrap();
+    let time: Entry = ui.get_object("due_time").unwrap();
+    let (year, month, day) = calendar.get_date();
+    let mut spec = format!("{:04}-{:02}-{:02}", year, month + 1, day);
+    let time = time.get_text().unwrap_or_default();
+    if !time.trim().is_empty() {
+        spec = format!("{}T{}", spec, time.trim());
     }
+    due::parse(&spec, Local::today().naive_local())
 }
 
-fn update_status(ui: &Builder, status: &'static str, enable_btn: bool) {
+// e.g. "Refreshing...", or "12 tasks, synced at 10:42" and the last error
+fn update_status(ui: &Ui) {
+    let sync = &ui.sync;
+    let mut status = if !sync.connected {
+        String::from("Connecting...")
+    } else if sync.syncing {
+        String::from("Refreshing...")
+    } else {
+        let tasks = match ui.tasks.borrow().len() {
+            1 => String::from("1 task"),
+            n => format!("{} tasks", n),
+        };
+        match sync.last {
+            Some(last) => {
+                format!("{}, synced at {}", tasks, last.format("%H:%M"))
+            }
+            None => tasks,
+        }
+    };
+    if let Some(ref error) = sync.error {
+        status.push_str(&format!(" (error: {})", error));
+    }
+
+    ui.builder
+        .get_object("status")
+        .and_then(|b: Statusbar| {
+            let context = b.get_context_id("status");
+            b.pop(context);
+            Some(b.push(context, &status))
+        });
+    ui.builder
+        .get_object("delete")
+        .and_then(|d: Button| Some(d.set_sensitive(sync.connected)));
+}
+
+fn push_status(ui: &Builder, status: &str) {
     ui.get_object("status")
         .and_then(|b: Statusbar| {
             Some(b.push(b.get_context_id("status"), status))
         });
-    ui.get_object("delete")
-        .and_then(|d: Button| Some(d.set_sensitive(enable_btn)));
 }
 
-fn destroy_checked() {
-    GLOBAL.with(|global| {
-        if let Some((ref ui, _, _)) = *global.borrow_mut() {
-            let lb: ListBox = ui.get_object("content").unwrap();
-            for wrow in lb.get_children() {
-                let row: ListBoxRow = wrow.downcast().unwrap();
-                let wcheck = row.get_child().unwrap();
-                let check: CheckButton = wcheck.downcast().unwrap();
-                let label = check.get_label().unwrap();
-                info!("Considering '{}'", label);
-
-                if check.get_active() {
-                    info!("Destroying '{}'", label);
-                    row.destroy();
+// The checked tasks, or the selected one if none is
+fn complete_checked(ui: &mut Ui) {
+    let lb: ListBox = ui.builder.get_object("content").unwrap();
+    let mut uids = Vec::new();
+    for wrow in lb.get_children() {
+        let row: ListBoxRow = wrow.downcast().unwrap();
+        let wcheck = row.get_child().unwrap();
+        let check: CheckButton = wcheck.downcast().unwrap();
+        let uid = row_uid(&row);
+        info!("Considering {:?}", uid);
+
+        if check.get_active() && row.get_child_visible() {
+            info!("Completing {:?}", uid);
+            uids.extend(uid);
+        }
+    }
+    if uids.is_empty() {
+        uids.extend(lb.get_selected_row().and_then(|r| row_uid(&r)));
+    }
+    complete_later(ui, uids);
+}
+
+// Completed tasks are hidden right away, but only sent to the poller once
+// the grace period is over without them being taken back. Completing the
+// root of a thread completes its replies as well.
+fn complete_later(ui: &mut Ui, mut uids: Vec<u64>) {
+    {
+        let tasks = ui.tasks.borrow();
+        let mut filter = ui.filter.borrow_mut();
+        let replies: Vec<u64> = tasks
+            .values()
+            .filter(|t| t.parent.map_or(false, |p| uids.contains(&p)))
+            .map(|t| t.uid)
+            .collect();
+        uids.extend(replies);
+        for uid in uids {
+            if let Some(task) = tasks.get(&uid) {
+                if filter.completed.insert(uid) {
+                    ui.pending.push(task.clone());
                 }
             }
         }
+    }
+    if ui.pending.is_empty() {
+        return;
+    }
+    refilter(ui);
+
+    let label: Label = ui.builder.get_object("undo_label").unwrap();
+    label.set_text(&match ui.pending.len() {
+        1 => String::from("1 task completed"),
+        n => format!("{} tasks completed", n),
+    });
+    let bar: gtk::InfoBar = ui.builder.get_object("undo_bar").unwrap();
+    bar.show();
+
+    // Every completion restarts the grace period of the whole batch
+    ui.batch += 1;
+    let batch = ui.batch;
+    glib::timeout_add_seconds(ui.grace, move || {
+        GLOBAL.with(|global| {
+            if let Some(ref mut ui) = *global.borrow_mut() {
+                if ui.batch == batch {
+                    commit_completions(ui);
+                }
+            }
+        });
+        glib::Continue(false)
+    });
+}
+
+fn commit_completions(ui: &mut Ui) {
+    for task in ui.pending.drain(..) {
+        if let Err(e) = ui.poller.send(Message::Delete(task.uid)) {
+            error!("Couldn't send delete message {}: {}", task.uid, e);
+        }
+        ui.own.remove(task.uid);
+        ui.done.push(task);
+    }
+    let excess = ui.done.len().saturating_sub(UNDO_HISTORY);
+    ui.done.drain(..excess);
+    let done = &ui.done;
+    ui.copies.retain(|uid, _| done.iter().any(|t| t.uid == *uid));
+
+    let bar: gtk::InfoBar = ui.builder.get_object("undo_bar").unwrap();
+    bar.hide();
+    let undo: Button = ui.builder.get_object("undo").unwrap();
+    undo.set_sensitive(!ui.done.is_empty());
+}
+
+// Brings back a task the poller couldn't complete, which is still there
+fn complete_failed(ui: &mut Ui, uid: u64, error: &str) {
+    ui.filter.borrow_mut().completed.remove(&uid);
+    ui.own.removed.remove(&uid);
+    ui.done.retain(|t| t.uid != uid);
+    ui.copies.remove(&uid);
+    let undo: Button = ui.builder.get_object("undo").unwrap();
+    undo.set_sensitive(!ui.done.is_empty());
+    refilter(ui);
+
+    let title = ui.tasks
+        .borrow()
+        .get(&uid)
+        .map_or_else(|| uid.to_string(), |t| format!("'{}'", t.title));
+    let msg = format!("Couldn't complete {}: {}", title, error);
+    push_status(&ui.builder, &msg);
+}
+
+// Takes back the completions still within the grace period, or else
+// restores the last task moved to the done folder
+fn undo(ui: &mut Ui) {
+    if ui.pending.is_empty() {
+        if let Some(task) = ui.done.pop() {
+            push_status(&ui.builder, &format!("Restoring '{}'", task.title));
+            // Along with it goes the copy a recurring task got
+            if let Some(copy) = ui.copies.remove(&task.uid) {
+                let tasks = ui.tasks.borrow();
+                for t in tasks.values().filter(|t| t.message_id == copy) {
+                    ui.own.remove(t.uid);
+                }
+            }
+            ui.own.add(&task.message_id);
+            let _ = ui.poller.send(Message::Restore(task.message_id));
+        }
+        let undo: Button = ui.builder.get_object("undo").unwrap();
+        undo.set_sensitive(!ui.done.is_empty());
+        return;
+    }
+
+    // Leaves the timer of the batch with nothing to do
+    ui.batch += 1;
+    let uids: HashSet<u64> = ui.pending.drain(..).map(|t| t.uid).collect();
+    ui.filter
+        .borrow_mut()
+        .completed
+        .retain(|uid| !uids.contains(uid));
+
+    let lb: ListBox = ui.builder.get_object("content").unwrap();
+    for wrow in lb.get_children() {
+        let row: ListBoxRow = wrow.downcast().unwrap();
+        if row_uid(&row).map_or(false, |uid| uids.contains(&uid)) {
+            let check = row.get_child().unwrap();
+            let check: CheckButton = check.downcast().unwrap();
+            check.set_active(false);
+        }
+    }
+    let bar: gtk::InfoBar = ui.builder.get_object("undo_bar").unwrap();
+    bar.hide();
+    refilter(ui);
+}
+
+fn row_uid(row: &ListBoxRow) -> Option<u64> {
+    row.get_child()?.get_name()?.parse().ok()
+}
+
+fn show_task(
+    ui: &Builder,
+    folder: &str,
+    uid: u64,
+    poller: &Sender<Message>,
+) {
+    let detail: gtk::Box = ui.get_object("detail").unwrap();
+    detail.show();
+
+    // The cache makes reopening a task instant, and only when it misses we
+    // ask the poller, which will answer with a Message::Details
+    match cache::load(folder, uid) {
+        Some(details) => show_details(ui, &details, poller),
+        None => {
+            show_details(
+                ui,
+                &Details {
+                    uid: uid,
+                    body: String::from("Loading..."),
+                    ..Default::default()
+                },
+                poller,
+            );
+            if let Err(e) = poller.send(Message::Fetch(uid)) {
+                error!("Couldn't request message {}: {}", uid, e);
+            }
+        }
+    }
+}
+
+fn show_details(
+    ui: &Builder,
+    details: &Details,
+    poller: &Sender<Message>,
+) {
+    // Answers to old selections are of no interest
+    let lb: ListBox = ui.get_object("content").unwrap();
+    if lb.get_selected_row().and_then(|r| row_uid(&r)) != Some(details.uid) {
+        return;
+    }
+
+    let from: Label = ui.get_object("detail_from").unwrap();
+    from.set_text(&format!("From: {}", details.from));
+    let date: Label = ui.get_object("detail_date").unwrap();
+    date.set_text(&format!("Date: {}", details.date));
+
+    let attachments: ListBox = ui.get_object("detail_attachments").unwrap();
+    for row in attachments.get_children() {
+        row.destroy();
+    }
+    for (index, attachment) in details.attachments.iter().enumerate() {
+        attachments.add(&attachment_row(
+            ui,
+            details.uid,
+            index,
+            &attachment.name,
+            &details::human_size(attachment.size),
+            poller,
+        ));
+    }
+    attachments.show_all();
+    attachments.set_visible(!details.attachments.is_empty());
+
+    let body: TextView = ui.get_object("detail_body").unwrap();
+    if let Some(buffer) = body.get_buffer() {
+        buffer.set_text(&details.body);
+    }
+}
+
+fn attachment_row(
+    ui: &Builder,
+    uid: u64,
+    index: usize,
+    name: &str,
+    size: &str,
+    poller: &Sender<Message>,
+) -> gtk::Box {
+    let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    let label = Label::new(Some(&format!("{} ({})", name, size)[..]));
+    label.set_halign(gtk::Align::Start);
+    row.pack_start(&label, true, true, 0);
+
+    // Opening goes through a temporary copy, and neither marks the
+    // message as \Seen
+    let open = Button::new_with_label("Open");
+    let tx = poller.clone();
+    open.connect_clicked(move |_| {
+        let _ = tx.send(Message::SaveAttachment(uid, index, None));
+    });
+    row.pack_start(&open, false, false, 0);
+
+    let save = Button::new_with_label("Save...");
+    let tx = poller.clone();
+    let ui = ui.clone();
+    let name = name.to_string();
+    save.connect_clicked(move |_| {
+        let dialog: FileChooserDialog = ui.get_object("save_dialog").unwrap();
+        dialog.set_current_name(&name);
+        let response = dialog.run();
+        dialog.hide();
+        if response != SAVE {
+            return;
+        }
+        if let Some(path) = dialog.get_filename() {
+            let _ = tx.send(Message::SaveAttachment(uid, index, Some(path)));
+        }
+    });
+    row.pack_start(&save, false, false, 0);
+    row
+}
+
+fn open_selected() {
+    GLOBAL.with(|global| {
+        if let Some(ref ui) = *global.borrow() {
+            let lb: ListBox = ui.builder.get_object("content").unwrap();
+            let uid = match lb.get_selected_row().and_then(|r| row_uid(&r)) {
+                Some(uid) => uid,
+                None => return,
+            };
+            let tasks = ui.tasks.borrow();
+            let task = match tasks.get(&uid) {
+                Some(task) => task,
+                None => return,
+            };
+            if let Err(e) = launcher::open(&ui.mail_client, task, &ui.folder) {
+                let msg = format!("Couldn't open the mail client: {}", e);
+                error!("{}", msg);
+                push_status(&ui.builder, &msg);
+            }
+        }
     });
 }
 
 fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options]", program);
+    let brief = format!("Usage: {} [options] [command]", program);
     print!("{}", opts.usage(&brief));
+    println!("\n{}", cli::USAGE);
 }
@@ -1,13 +1,498 @@
+use native_tls::TlsConnector;
 use notify_rust::Notification;
+use serde_json;
 
-pub fn notify(body: &str, icon: &str, timeout: i32) {
-    if let Err(e) = Notification::new()
-        .summary(::NAME)
-        .body(body)
-        .icon(icon)
-        .timeout(timeout)
-        .show()
-    {
-        error!("Couldn't show notification: {:?}", e)
+use {launcher, Message, Result, Task};
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    // How many tasks there are, after the first sync
+    Pending,
+    Added,
+    // Several tasks added, completed or removed at once
+    Changes,
+    Reminder,
+    // What was held during quiet hours
+    Digest,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Kind::Pending => "pending",
+            Kind::Added => "added",
+            Kind::Changes => "changes",
+            Kind::Reminder => "reminder",
+            Kind::Digest => "digest",
+        }
+    }
+}
+
+// What every notifier gets, and what the command and webhook ones get as
+// JSON
+#[derive(Clone, Debug, Serialize)]
+pub struct Note {
+    pub kind: Kind,
+    pub summary: String,
+    pub body: String,
+    // The task it's about, when it's about a single one
+    pub task: Option<Task>,
+}
+
+pub trait Notifier {
+    fn notify(&self, note: &Note) -> Result<()>;
+
+    // Whether it's held back while the desktop doesn't want to be
+    // disturbed
+    fn is_desktop(&self) -> bool {
+        false
+    }
+}
+
+// One notifier failing doesn't keep the note from the others. While the
+// desktop doesn't want to be disturbed, the desktop notifiers are left out.
+pub fn notify_all(notifiers: &[Box<Notifier>], note: &Note, dnd: bool) {
+    notify(notifiers.iter().filter(|n| !dnd || !n.is_desktop()), note);
+}
+
+// What the desktop notifiers held for when it can be disturbed again
+pub fn notify_desktop(notifiers: &[Box<Notifier>], note: &Note) {
+    notify(notifiers.iter().filter(|n| n.is_desktop()), note);
+}
+
+fn notify<'a, I>(notifiers: I, note: &Note)
+where
+    I: Iterator<Item = &'a Box<Notifier>>,
+{
+    for notifier in notifiers {
+        if let Err(e) = notifier.notify(note) {
+            error!("Couldn't notify '{}': {}", note.summary, e);
+        }
+    }
+}
+
+// A notifier given as "desktop", "log", "command:COMMAND" or "webhook:URL".
+// The answers to the buttons of desktop notifications are sent to "tx".
+pub fn parse(spec: &str, tx: &Sender<Message>) -> Result<Box<Notifier>> {
+    let (name, arg) = match spec.find(':') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => (spec, ""),
+    };
+    match name {
+        "desktop" => Ok(Box::new(Desktop {
+            tx: tx.clone(),
+            waiting: Arc::new(AtomicUsize::new(0)),
+        })),
+        "log" => Ok(Box::new(Log)),
+        "command" => {
+            if launcher::split(arg)?.is_empty() {
+                return Err("The command notifier needs a command".into());
+            }
+            Ok(Box::new(Run {
+                command: arg.to_string(),
+            }))
+        }
+        "webhook" => {
+            if !arg.starts_with("http://") && !arg.starts_with("https://") {
+                return Err("The webhook notifier needs an http(s) URL".into());
+            }
+            Ok(Box::new(Webhook::new(arg)?))
+        }
+        _ => Err(format!("Unknown notifier '{}'", spec).into()),
+    }
+}
+
+// Popups, with buttons for notes about a task
+pub struct Desktop {
+    tx: Sender<Message>,
+    // How many popups are waiting for a button
+    waiting: Arc<AtomicUsize>,
+}
+
+// Popups with buttons waiting at once, each on a thread, past which they
+// come without buttons
+const WAITING: usize = 4;
+
+impl Notifier for Desktop {
+    fn notify(&self, note: &Note) -> Result<()> {
+        match note.task {
+            Some(ref task) if self.waiting.load(Ordering::SeqCst) < WAITING => {
+                notify_task(
+                    &note.summary,
+                    &note.body,
+                    task.uid,
+                    self.tx.clone(),
+                    self.waiting.clone(),
+                )
+            }
+            _ => {
+                Notification::new()
+                    .summary(&note.summary)
+                    .body(&note.body)
+                    .icon(::ICON)
+                    .timeout(::NOTIF_TIMEOUT)
+                    .show()
+                    .map_err(|e| format!("{:?}", e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_desktop(&self) -> bool {
+        true
+    }
+}
+
+// A notification about a task, with buttons to complete it, snooze its
+// reminders for an hour or open it in the window. Waiting for the answer
+// blocks, so it's all done from a thread of its own, counted in "waiting"
+// until the notification is gone.
+fn notify_task(
+    summary: &str,
+    body: &str,
+    uid: u64,
+    tx: Sender<Message>,
+    waiting: Arc<AtomicUsize>,
+) {
+    let summary = summary.to_string();
+    let body = body.to_string();
+    waiting.fetch_add(1, Ordering::SeqCst);
+    let counted = waiting.clone();
+    let result = thread::Builder::new()
+        .name(format!("notification {}", uid))
+        .spawn(move || {
+            let handle = match Notification::new()
+                .summary(&summary)
+                .body(&body)
+                .icon(::ICON)
+                .action("done", "Done")
+                .action("snooze", "Snooze 1h")
+                .action("open", "Open")
+                .action("default", "Open")
+                .show()
+            {
+                Ok(handle) => handle,
+                Err(e) => {
+                    error!("Couldn't show notification: {:?}", e);
+                    counted.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+            };
+            handle.wait_for_action(|action| {
+                let msg = match action {
+                    "done" => Message::Complete(uid),
+                    "snooze" => Message::Snooze(uid),
+                    "open" | "default" => Message::Open(uid),
+                    _ => return,
+                };
+                let _ = tx.send(msg);
+            });
+            counted.fetch_sub(1, Ordering::SeqCst);
+        });
+    if let Err(e) = result {
+        error!("Couldn't start the notification thread: {}", e);
+        waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// A line on stderr, for the journal when running as a service
+pub struct Log;
+
+impl Notifier for Log {
+    fn notify(&self, note: &Note) -> Result<()> {
+        let body = note.body.replace('\n', "; ");
+        if body.is_empty() {
+            eprintln!("{}: {}", note.kind.name(), note.summary);
+        } else {
+            eprintln!("{}: {}: {}", note.kind.name(), note.summary, body);
+        }
+        Ok(())
+    }
+}
+
+// Runs a command with the note in MAIL_TODO_* variables, and as JSON on
+// its standard input
+pub struct Run {
+    command: String,
+}
+
+impl Notifier for Run {
+    fn notify(&self, note: &Note) -> Result<()> {
+        let words = launcher::split(&self.command)?;
+        let (program, args) = words.split_first().ok_or("Empty command")?;
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .stdin(Stdio::piped())
+            .env("MAIL_TODO_KIND", note.kind.name())
+            .env("MAIL_TODO_SUMMARY", &note.summary)
+            .env("MAIL_TODO_BODY", &note.body);
+        if let Some(ref task) = note.task {
+            command
+                .env("MAIL_TODO_UID", task.uid.to_string())
+                .env("MAIL_TODO_TITLE", &task.title);
+        }
+
+        let json = serde_json::to_vec(note)?;
+        let mut child = command.spawn()?;
+        let mut stdin = child.stdin.take().ok_or("No standard input")?;
+        // The command may take its time reading it, and has to be waited
+        // for so it doesn't linger as a zombie
+        thread::spawn(move || {
+            if let Err(e) = stdin.write_all(&json) {
+                warn!("Couldn't write the note to the command: {}", e);
+            }
+            drop(stdin);
+            let _ = child.wait();
+        });
+        Ok(())
+    }
+}
+
+// POSTs the note as JSON, one note after the other from a thread of its
+// own, so a slow webhook doesn't hold up anything else
+pub struct Webhook {
+    tx: Sender<String>,
+}
+
+impl Webhook {
+    fn new(url: &str) -> Result<Webhook> {
+        let (tx, rx) = channel::<String>();
+        let url = url.to_string();
+        thread::Builder::new()
+            .name("webhook".to_string())
+            .spawn(move || {
+                for body in rx {
+                    if let Err(e) = post(&url, &body) {
+                        error!("Couldn't post to {}: {}", url, e);
+                    }
+                }
+            })?;
+        Ok(Webhook { tx: tx })
+    }
+}
+
+impl Notifier for Webhook {
+    fn notify(&self, note: &Note) -> Result<()> {
+        let body = serde_json::to_string(note)?;
+        self.tx
+            .send(body)
+            .map_err(|_| "The webhook thread is gone".into())
+    }
+}
+
+// Just enough HTTP to POST some JSON and check the answer's status
+fn post(url: &str, body: &str) -> Result<()> {
+    let (tls, rest) = if url.starts_with("https://") {
+        (true, &url["https://".len()..])
+    } else if url.starts_with("http://") {
+        (false, &url["http://".len()..])
+    } else {
+        return Err("Only http and https URLs are supported".into());
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = host_port(authority)?;
+    let port = port.unwrap_or(if tls { 443 } else { 80 });
+
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        body.len(),
+        body
+    );
+    let stream = connect(host, port)?;
+    stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+    let status = if tls {
+        let stream = TlsConnector::new()?.connect(host, stream)?;
+        exchange(stream, &request)?
+    } else {
+        exchange(stream, &request)?
+    };
+
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("The webhook answered '{}'", status.trim()).into()),
+    }
+}
+
+// Seconds to wait for the webhook to connect, take the request or answer
+const TIMEOUT: u64 = 30;
+
+// The host and port of "host:port", "[::1]:port" or either without a port
+fn host_port(authority: &str) -> Result<(&str, Option<u16>)> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority.find(']').ok_or("Unclosed '[' in the URL")?;
+        let port = &authority[end + 1..];
+        if !port.is_empty() && !port.starts_with(':') {
+            return Err("Junk after the IPv6 address in the URL".into());
+        }
+        (&authority[1..end], port.get(1..))
+    } else {
+        match authority.rfind(':') {
+            Some(i) => (&authority[..i], Some(&authority[i + 1..])),
+            None => (authority, None),
+        }
+    };
+    match port {
+        Some(port) => Ok((host, Some(port.parse::<u16>()?))),
+        None => Ok((host, None)),
+    }
+}
+
+// Tries every address the host has, giving each TIMEOUT seconds
+fn connect(host: &str, port: u16) -> Result<TcpStream> {
+    let mut last = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, Duration::from_secs(TIMEOUT)) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = Some(e),
+        }
+    }
+    match last {
+        Some(e) => Err(e.into()),
+        None => Err(format!("No address for {}", host).into()),
+    }
+}
+
+// Sends the request and returns the status line of the response
+fn exchange<S: Read + Write>(mut stream: S, request: &str) -> Result<String> {
+    stream.write_all(request.as_bytes())?;
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    Ok(status)
+}
+
+// Several notes in one, listing their summaries
+pub fn digest(notes: &[Note]) -> Note {
+    let mut body = notes
+        .iter()
+        .take(LISTED)
+        .map(|n| n.summary.clone())
+        .collect::<Vec<String>>()
+        .join("\n");
+    if notes.len() > LISTED {
+        body.push_str(&format!("\nand {} more", notes.len() - LISTED));
+    }
+    Note {
+        kind: Kind::Digest,
+        summary: format!("{} notifications while quiet", notes.len()),
+        body: body,
+        task: None,
+    }
+}
+
+// Changes to the folder gathered for a single notification
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub added: Vec<Task>,
+    pub removed: Vec<Task>,
+    // Completed from somewhere else than the window, like the control
+    // socket or the HTTP API
+    pub completed: Vec<Task>,
+}
+
+// Titles listed in a notification before leaving the rest out
+const LISTED: usize = 5;
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.completed.is_empty()
+    }
+
+    // A single new task makes a note about it, anything else one listing
+    // all of it, e.g. "2 new tasks, 1 completed elsewhere"
+    pub fn note(&self) -> Option<Note> {
+        if self.added.len() == 1
+            && self.removed.is_empty()
+            && self.completed.is_empty()
+        {
+            let task = &self.added[0];
+            let body = if task.sender().is_empty() {
+                String::new()
+            } else {
+                format!("From {}", task.sender())
+            };
+            return Some(Note {
+                kind: Kind::Added,
+                summary: format!("New task: {}", task.title),
+                body: body,
+                task: Some(task.clone()),
+            });
+        }
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut summary = Vec::new();
+        match self.added.len() {
+            0 => (),
+            1 => summary.push(String::from("1 new task")),
+            n => summary.push(format!("{} new tasks", n)),
+        }
+        if !self.completed.is_empty() {
+            let n = self.completed.len();
+            summary.push(format!("{} completed elsewhere", n));
+        }
+        if !self.removed.is_empty() {
+            summary.push(format!("{} removed", self.removed.len()));
+        }
+
+        let lines: Vec<String> = self.added
+            .iter()
+            .map(|t| format!("New: {}", t.title))
+            .chain(self.completed.iter().map(|t| format!("Done: {}", t.title)))
+            .chain(self.removed.iter().map(|t| format!("Gone: {}", t.title)))
+            .collect();
+        let mut body = lines[..lines.len().min(LISTED)].join("\n");
+        if lines.len() > LISTED {
+            body.push_str(&format!("\nand {} more", lines.len() - LISTED));
+        }
+
+        Some(Note {
+            kind: Kind::Changes,
+            summary: summary.join(", "),
+            body: body,
+            task: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_ports() {
+        assert_eq!(host_port("example.com").unwrap(), ("example.com", None));
+        assert_eq!(
+            host_port("example.com:8080").unwrap(),
+            ("example.com", Some(8080))
+        );
+        assert_eq!(host_port("[::1]").unwrap(), ("::1", None));
+        assert_eq!(
+            host_port("[fe80::1]:8080").unwrap(),
+            ("fe80::1", Some(8080))
+        );
+        assert!(host_port("[::1").is_err());
+        assert!(host_port("[::1]8080").is_err());
+        assert!(host_port("example.com:http").is_err());
     }
 }
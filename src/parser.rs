@@ -4,7 +4,7 @@ use regex::Regex;
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use {Creds, Result};
 
 pub fn get_credentials(conf: String) -> Result<Creds> {
@@ -19,12 +19,20 @@ pub fn get_credentials(conf: String) -> Result<Creds> {
     let host = extract_info(r"set folder=imaps?://(.+):\d+", &content)?;
     let port = extract_info(r"set folder=imaps?://.+:(\d+)", &content)?;
     let port = port.parse()?;
+    let from = extract_info(r#"set from=["']?([^"'\s]+)"#, &content).ok();
+    let smtp_url =
+        extract_info(r#"set smtp_url=["']?([^"'\s]+)"#, &content).ok();
+    let smtp_pass =
+        extract_info(r#"set smtp_pass=["']?([^"'\s]+)"#, &content).ok();
 
     Ok(Creds {
         user: user,
         pass: pass,
         host: host,
         port: port,
+        from: from,
+        smtp_url: smtp_url,
+        smtp_pass: smtp_pass,
     })
 }
 
@@ -49,3 +57,33 @@ pub fn get_db_path() -> Result<String> {
         .ok_or("Can't convert path into string")?;
     Ok(path_str.to_string())
 }
+
+pub fn get_cache_dir() -> Result<PathBuf> {
+    let mut path = dirs::home_dir().ok_or("Can't get home dir")?;
+    path.push(::CACHE);
+    Ok(path)
+}
+
+pub fn get_view_path() -> Result<PathBuf> {
+    let mut path = dirs::home_dir().ok_or("Can't get home dir")?;
+    path.push(::VIEW);
+    Ok(path)
+}
+
+pub fn get_shortcuts_path() -> Result<PathBuf> {
+    let mut path = dirs::home_dir().ok_or("Can't get home dir")?;
+    path.push(::SHORTCUTS);
+    Ok(path)
+}
+
+pub fn get_digest_path() -> Result<PathBuf> {
+    let mut path = dirs::home_dir().ok_or("Can't get home dir")?;
+    path.push(::DIGEST);
+    Ok(path)
+}
+
+pub fn get_completed_path() -> Result<PathBuf> {
+    let mut path = dirs::home_dir().ok_or("Can't get home dir")?;
+    path.push(::COMPLETED);
+    Ok(path)
+}
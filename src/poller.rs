@@ -1,17 +1,24 @@
 extern crate libresolv_sys;
 extern crate native_tls;
 
-use email;
 use imap;
 use imap::client::Session;
 use self::native_tls::{TlsConnector, TlsStream};
+use chrono::{DateTime, FixedOffset, Local};
 
-use {parser, Creds, Message, Result, Task};
+use details::{self, Details};
+use digest::{self, Request, Via};
+use priority::{self, Priority};
+use recurrence::Rule;
+use response::{self, Value};
+use {cache, due, envelope, instance, launcher, search, smtp, tags, threads,
+     Creds, Message, Result, Task};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::net::TcpStream;
-use std::str;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread::sleep;
 use std::time::Duration;
@@ -19,13 +26,18 @@ use std::time::Duration;
 pub fn start(
     creds: Creds,
     folder: &str,
-    ui: Sender<Message>,
+    done: &str,
+    ui: Vec<Sender<Message>>,
     wake: Sender<Message>,
     rx: Receiver<Message>,
 ) {
     let mut slept = 0;
     //let mut imap: Option<Client<TlsStream<TcpStream>>> = None;
     let mut imap: Option<Session<TlsStream<TcpStream>>> = None;
+    // Whether the server has Gmail's labels, asked once per connection
+    let mut gmail = false;
+    // Completions waiting for a connection
+    let mut queued: Vec<u64> = Vec::new();
 
     debug!("Sending 'connect' message");
     let _ = wake.send(Message::Connect);
@@ -33,19 +45,168 @@ pub fn start(
     while let Ok(m) = rx.recv() {
         match m {
             Message::Quit => {
+                if !queued.is_empty() {
+                    warn!("Never connected to complete {:?}", queued);
+                }
                 imap.and_then(|mut imap| imap.logout().ok());
                 break;
             }
             Message::Delete(uid) => if let Some(ref mut imap) = imap {
-                delete_task(imap, uid);
-                let _ = wake.send(Message::Awake);
+                match complete_task(imap, &folder, uid, &done) {
+                    Ok(copy) => {
+                        if let Some(id) = copy {
+                            broadcast(&ui, Message::Replaced(uid, id));
+                        }
+                        broadcast(&ui, Message::Completed(uid))
+                    }
+                    Err(e) => {
+                        error!("Couldn't complete task {}: {}", uid, e);
+                        let msg = Message::CompleteFailed(uid, e.to_string());
+                        broadcast(&ui, msg);
+                    }
+                }
+                let _ = wake.send(Message::Refresh);
+            } else {
+                info!("Not connected, completing {} once connected", uid);
+                queued.push(uid);
+            },
+            Message::Edit(uid, title) => if let Some(ref mut imap) = imap {
+                if let Err(e) = edit_task(imap, &folder, uid, &title) {
+                    error!("Couldn't edit task {}: {}", uid, e);
+                }
+                let _ = wake.send(Message::Refresh);
+            },
+            Message::SetDue(uid, date) => if let Some(ref mut imap) = imap {
+                if let Err(e) = set_due(imap, &folder, uid, date) {
+                    error!("Couldn't set the due date of {}: {}", uid, e);
+                }
+                let _ = wake.send(Message::Refresh);
+            },
+            Message::SetPriority(uid, priority) => {
+                if let Some(ref mut imap) = imap {
+                    match set_priority(imap, &folder, uid, priority) {
+                        Ok(Some(id)) => {
+                            broadcast(&ui, Message::Replaced(uid, id))
+                        }
+                        Ok(None) => (),
+                        Err(e) => error!(
+                            "Couldn't set the priority of {}: {}",
+                            uid, e
+                        ),
+                    }
+                    let _ = wake.send(Message::Refresh);
+                }
+            }
+            Message::AddTag(uid, tag) => if let Some(ref mut imap) = imap {
+                match add_tag(imap, &folder, uid, &tag) {
+                    Ok(Some(id)) => broadcast(&ui, Message::Replaced(uid, id)),
+                    Ok(None) => (),
+                    Err(e) => error!("Couldn't tag {} '{}': {}", uid, tag, e),
+                }
+                let _ = wake.send(Message::Refresh);
+            },
+            Message::RemoveTag(uid, tag) => if let Some(ref mut imap) = imap {
+                match remove_tag(imap, &folder, uid, &tag, gmail) {
+                    Ok(Some(id)) => broadcast(&ui, Message::Replaced(uid, id)),
+                    Ok(None) => (),
+                    Err(e) => {
+                        error!("Couldn't untag {} '{}': {}", uid, tag, e)
+                    }
+                }
+                let _ = wake.send(Message::Refresh);
             },
+            Message::Restore(id) => if let Some(ref mut imap) = imap {
+                if let Err(e) = restore_task(imap, &folder, &done, &id) {
+                    error!("Couldn't restore task <{}>: {}", id, e);
+                }
+                let _ = wake.send(Message::Refresh);
+            },
+            Message::Add(title, id) => if let Some(ref mut imap) = imap {
+                if let Err(e) = add_task(imap, &creds, &folder, &title, &id) {
+                    error!("Couldn't add task '{}': {}", title, e);
+                }
+                let _ = wake.send(Message::Refresh);
+            } else {
+                warn!("Not connected, dropping new task '{}'", title);
+            },
+            Message::SendDigest(request) => match imap {
+                Some(ref mut imap) => {
+                    let digest =
+                        send_digest(imap, &creds, &folder, &request, gmail);
+                    match digest {
+                        Ok(sent) => {
+                            let sent = Message::DigestSent(sent);
+                            let _ = request.reply.send(sent);
+                        }
+                        Err(e) => error!("Couldn't send the digest: {}", e),
+                    }
+                }
+                None => warn!("Not connected, leaving the digest for later"),
+            },
+            Message::Fetch(uid) => if let Some(ref mut imap) = imap {
+                match fetch_details(imap, &folder, uid) {
+                    Ok(details) => broadcast(&ui, Message::Details(details)),
+                    Err(e) => error!("Couldn't fetch message {}: {}", uid, e),
+                }
+            },
+            Message::SaveAttachment(uid, index, dest) => {
+                if let Some(ref mut imap) = imap {
+                    match save_attachment(imap, uid, index, dest) {
+                        Ok(path) => broadcast(&ui, Message::Saved(path)),
+                        Err(e) => error!("Couldn't save attachment: {}", e),
+                    }
+                }
+            }
+            Message::Search(terms) => if let Some(ref mut imap) = imap {
+                let mut found = HashMap::new();
+                for term in terms {
+                    match search_body(imap, &term) {
+                        Ok(uids) => {
+                            found.insert(term, uids);
+                        }
+                        Err(e) => {
+                            error!("Couldn't search for '{}': {}", term, e)
+                        }
+                    }
+                }
+                broadcast(&ui, Message::Found(found));
+            },
+            Message::Refresh => {
+                // Unlike Awake, this doesn't start another Sleep chain: the
+                // one already running gets its counter reset, and if the
+                // connection is broken its next Awake will reconnect
+                let broken = match imap {
+                    Some(ref mut imap) => {
+                        match sync(imap, &folder, gmail, &ui) {
+                            Ok(tasks) => {
+                                broadcast(&ui, Message::Tasks(tasks));
+                                slept = 0;
+                                false
+                            }
+                            Err(e) => {
+                                error!("Error getting tasks: {}", e);
+                                true
+                            }
+                        }
+                    }
+                    None => {
+                        let msg = String::from("Not connected");
+                        broadcast(&ui, Message::SyncError(msg));
+                        false
+                    }
+                };
+                if broken {
+                    imap = None;
+                }
+            }
             Message::Awake => if let Some(ref mut imap) = imap {
-                match get_tasks(imap, &folder) {
+                match sync(imap, &folder, gmail, &ui) {
                     Ok(tasks) => {
-                        if let Err(e) = ui.send(Message::Tasks(tasks)) {
-                            panic!("Main thread receiver deallocated: {}", e);
+                        let uids = tasks.iter().map(|t| t.uid).collect();
+                        if let Err(e) = cache::prune(&folder, &uids) {
+                            warn!("Couldn't prune the cache: {}", e);
                         }
+                        broadcast(&ui, Message::Tasks(tasks));
                         debug!("Sending sleep message from awake");
                         let _ = wake.send(Message::Sleep);
                         slept = 0;
@@ -61,20 +222,25 @@ pub fn start(
             },
             Message::Connect => {
                 info!("Setting as disconnected");
-                if let Err(e) = ui.send(Message::NotConnected) {
-                    error!("Couldn't set the status: {}", e);
-                }
+                broadcast(&ui, Message::NotConnected);
 
                 imap = match get_connection(&creds) {
                     Err(e) => {
                         error!("Error getting connection: {:?}", e);
+                        let msg = format!("Couldn't connect: {}", e);
+                        broadcast(&ui, Message::SyncError(msg));
                         let _ = wake.send(Message::Sleep);
                         None
                     }
                     Ok(mut imap) => {
                         info!("Connected!");
-                        if let Err(e) = ui.send(Message::Connected) {
-                            error!("Couldn't set the status: {}", e);
+                        gmail = is_gmail(&mut imap).unwrap_or_else(|e| {
+                            warn!("Couldn't ask for capabilities: {}", e);
+                            false
+                        });
+                        broadcast(&ui, Message::Connected);
+                        for uid in queued.drain(..) {
+                            let _ = wake.send(Message::Delete(uid));
                         }
                         let _ = wake.send(Message::Awake);
                         Some(imap)
@@ -97,7 +263,15 @@ pub fn start(
     info!("Exiting poller thread");
 }
 
-fn get_connection(creds: &Creds) -> Result<Session<TlsStream<TcpStream>>> {
+fn broadcast(listeners: &[Sender<Message>], msg: Message) {
+    for l in listeners {
+        if let Err(e) = l.send(msg.clone()) {
+            error!("Couldn't send {:?} to listener: {}", msg, e);
+        }
+    }
+}
+
+pub fn get_connection(creds: &Creds) -> Result<Session<TlsStream<TcpStream>>> {
     // Here be dragons.
     // Whenever the thread tries to resolve the mail server domain it will
     // cache the domain name servers used to resolve that. If it happens to try
@@ -118,71 +292,657 @@ fn get_connection(creds: &Creds) -> Result<Session<TlsStream<TcpStream>>> {
         &ssl,
     )?;
     debug!("Logging in");
-    let session = imap.login(&creds.user, &creds.pass).unwrap();
+    // A wrong password is an error to show, not a reason to die
+    let session = imap
+        .login(&creds.user, &creds.pass)
+        .map_err(|(e, _)| e)?;
     debug!("Done!");
     Ok(session)
 }
 
-fn get_tasks<T: Read + Write>(
+// get_tasks, letting the listeners know it's going on and how it went
+fn sync<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    gmail: bool,
+    listeners: &[Sender<Message>],
+) -> Result<HashSet<Task>> {
+    broadcast(listeners, Message::Syncing);
+    let tasks = get_tasks(imap, folder, gmail);
+    if let Err(ref e) = tasks {
+        broadcast(listeners, Message::SyncError(e.to_string()));
+    }
+    tasks
+}
+
+// With "gmail", from is_gmail, it gets the labels too
+pub fn get_tasks<T: Read + Write>(
     imap: &mut Session<T>,
     folder: &str,
+    gmail: bool,
 ) -> Result<HashSet<Task>> {
     debug!("Getting tasks");
-    let mut tasks: HashSet<Task> = HashSet::new();
+    let mut tasks: Vec<Task> = Vec::new();
     let mbox = imap.select(folder)?;
-    for seqn in 1..mbox.exists + 1 {
-        let seq = &seqn.to_string();
-        let uid = get_uid(imap, seq)?;
-        match get_subj(imap, seq) {
-            Ok(s) => tasks.insert(Task {
-                title: s,
-                uid: uid,
-            }),
-            Err(e) => {
-                error!("{:?}", e);
-                true
-            }
+    if mbox.exists == 0 {
+        return Ok(HashSet::new());
+    }
+
+    // Two round trips for the whole folder: one for what the server knows
+    // about each message, and another one for the headers
+    let command = if gmail {
+        "FETCH 1:* (UID FLAGS RFC822.SIZE INTERNALDATE X-GM-LABELS)"
+    } else {
+        "FETCH 1:* (UID FLAGS RFC822.SIZE INTERNALDATE)"
+    };
+    let raw = imap.run_command_and_read_response(command)?;
+    let mut meta = envelope::parse_meta(&raw);
+
+    let fetch = imap.fetch("1:*", "(UID BODY.PEEK[HEADER])")?;
+    for f in fetch.iter() {
+        let uid = match f.uid {
+            Some(uid) => uid as u64,
+            None => continue,
         };
+        match f.body() {
+            Some(headers) => {
+                let m = meta.remove(&uid).unwrap_or_default();
+                tasks.push(envelope::build_task(uid, headers, m));
+            }
+            None => error!("No headers for message {}", uid),
+        }
     }
+    // Replies to a task are parts of it
+    threads::link(&mut tasks);
     debug!("Retrieved tasks: {:?}", tasks);
-    Ok(tasks)
+    Ok(tasks.into_iter().collect())
 }
 
-fn get_uid<T: Read + Write>(imap: &mut Session<T>, seq: &str) -> Result<u64> {
-    let resp = imap.fetch(seq, "uid")?;
-    let uid = resp[0].uid.unwrap() as u64;
-    Ok(uid)
+// Gmail has labels besides keywords
+pub fn is_gmail<T: Read + Write>(imap: &mut Session<T>) -> Result<bool> {
+    let raw = imap.run_command_and_read_response("CAPABILITY")?;
+    let response = String::from_utf8_lossy(&raw);
+    let gmail = response
+        .lines()
+        .filter(|l| l.starts_with("* CAPABILITY"))
+        .flat_map(|l| l.split_whitespace())
+        .any(|c| c.eq_ignore_ascii_case("X-GM-EXT-1"));
+    Ok(gmail)
 }
 
-fn get_subj<T: Read + Write>(
+// BODY.PEEK so looking at a task doesn't mark the message as \Seen
+fn fetch_raw<T: Read + Write>(
     imap: &mut Session<T>,
-    seq: &str,
-) -> Result<String> {
-    let fetch = imap.fetch(seq, "body[header]")?;
-    let lines = fetch.into_iter();
+    uid: u64,
+) -> Result<Vec<u8>> {
+    let fetch = imap.uid_fetch(&uid.to_string(), "BODY.PEEK[]")?;
+    let raw = fetch
+        .iter()
+        .filter_map(|f| f.body())
+        .next()
+        .ok_or("Message not found")?;
+    Ok(raw.to_vec())
+}
 
-    let mut headers = String::new();
-    for line in lines {
-        headers = headers + str::from_utf8(line.body().unwrap()).unwrap();
+// The headers shown, the BODYSTRUCTURE, and then only the part with the
+// text, as attachments are fetched when they're opened
+fn fetch_details<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+) -> Result<Details> {
+    let items = "BODYSTRUCTURE BODY.PEEK[HEADER.FIELDS (FROM DATE)]";
+    let fetch = fetch_items(imap, uid, items)?;
+    let headers = section(&fetch).unwrap_or(&[]);
+    let parts = fetch
+        .get("BODYSTRUCTURE")
+        .map(details::parts)
+        .unwrap_or_default();
+    let body = match details::body_part(&parts) {
+        Some(part) => Some(fetch_section(imap, uid, &part.section)?),
+        None => None,
+    };
+    let body = body.as_ref().map(|b| &b[..]);
+
+    let details = details::parse(uid, headers, &parts, body)?;
+    if let Err(e) = cache::store(folder, &details) {
+        warn!("Couldn't cache message {}: {}", uid, e);
     }
+    Ok(details)
+}
 
-    let mut subject = String::new();
-    let subj = parser::extract_info(r"\nSubject: ?(.*?)\r", &headers)?;
-    for word in subj.split_whitespace() {
-        match email::rfc2047::decode_rfc2047(&word) {
-            Some(decoded) => {
-                info!("Shit decoded: {:?}", word);
-                subject.push_str(&decoded);
-            }
-            None => subject.push_str(word),
+// Items of a message read with our own parser, so any of them can be asked
+// for, like the BODYSTRUCTURE
+fn fetch_items<T: Read + Write>(
+    imap: &mut Session<T>,
+    uid: u64,
+    items: &str,
+) -> Result<HashMap<String, Value>> {
+    let command = format!("UID FETCH {} (UID {})", uid, items);
+    let raw = imap.run_command_and_read_response(&command)?;
+    // There may be unsolicited ones about other messages
+    let fetch = response::fetches(&raw)
+        .into_iter()
+        .find(|f| f.get("UID").and_then(|u| u.number()) == Some(uid))
+        .ok_or("Message not found")?;
+    Ok(fetch)
+}
+
+// The BODY[...] section among fetched items, however the server spelled
+// its name
+fn section(fetch: &HashMap<String, Value>) -> Option<&[u8]> {
+    fetch
+        .iter()
+        .find(|&(name, _)| name.starts_with("BODY["))
+        .and_then(|(_, value)| value.bytes())
+}
+
+// A single part of a message, still encoded
+fn fetch_section<T: Read + Write>(
+    imap: &mut Session<T>,
+    uid: u64,
+    part: &str,
+) -> Result<Vec<u8>> {
+    let fetch = fetch_items(imap, uid, &format!("BODY.PEEK[{}]", part))?;
+    let data = section(&fetch).ok_or("Part not found")?;
+    Ok(data.to_vec())
+}
+
+// The selected folder is the one of the tasks, as get_tasks selects it
+fn search_body<T: Read + Write>(
+    imap: &mut Session<T>,
+    term: &str,
+) -> Result<HashSet<u64>> {
+    let criteria = search::criteria(term).ok_or("Can't search for that")?;
+    let command = format!("UID SEARCH {}", criteria);
+    let raw = imap.run_command_and_read_response(&command)?;
+    Ok(search::parse_response(&String::from_utf8_lossy(&raw)))
+}
+
+// Without a destination the attachment goes to a temporary directory and
+// is opened from there
+fn save_attachment<T: Read + Write>(
+    imap: &mut Session<T>,
+    uid: u64,
+    index: usize,
+    dest: Option<PathBuf>,
+) -> Result<PathBuf> {
+    let fetch = fetch_items(imap, uid, "BODYSTRUCTURE")?;
+    let parts = fetch
+        .get("BODYSTRUCTURE")
+        .map(details::parts)
+        .unwrap_or_default();
+    let part = *details::attachments(&parts)
+        .get(index)
+        .ok_or("No such attachment")?;
+    let name = part.name.clone().unwrap_or_default();
+    let data = fetch_section(imap, uid, &part.section)?;
+    let data = details::decode(part, &data)?;
+
+    let open = dest.is_none();
+    let (path, mut file) = match dest {
+        // Overwriting was confirmed in the file chooser
+        Some(path) => {
+            let file = File::create(&path)?;
+            (path, file)
         }
-        subject.push(' ');
-    }
+        None => {
+            let mut path =
+                instance::private_dir(&format!("mail-todo-{}", uid))?;
+            // Never trust a file name coming from an email
+            let name = Path::new(&name)
+                .file_name()
+                .map(|n| n.to_owned())
+                .unwrap_or_else(|| "attachment".into());
+            path.push(name);
+            // create_new doesn't follow symlinks
+            let file =
+                OpenOptions::new().write(true).create_new(true).open(&path)?;
+            (path, file)
+        }
+    };
+    file.write_all(&data)?;
+    info!("Saved {} to {:?}", name, path);
 
-    Ok(subject)
+    if open {
+        launcher::open_file(&path)?;
+    }
+    Ok(path)
 }
 
-fn delete_task<T: Read + Write>(imap: &mut Session<T>, uid: u64) {
+pub fn delete_task<T: Read + Write>(imap: &mut Session<T>, uid: u64) {
     let _ = imap.uid_store(&format!("{}", uid), "+FLAGS (\\Deleted)");
     let _ = imap.expunge();
 }
+
+// The task as get_tasks would make it, from the headers of the message
+fn fetch_task<T: Read + Write>(
+    imap: &mut Session<T>,
+    uid: u64,
+) -> Result<Task> {
+    let fetch = imap.uid_fetch(&uid.to_string(), "BODY.PEEK[HEADER]")?;
+    let headers = fetch
+        .iter()
+        .filter_map(|f| f.body())
+        .next()
+        .ok_or("Message not found")?;
+    let command = format!("UID FETCH {} (UID FLAGS)", uid);
+    let response = imap.run_command_and_read_response(&command)?;
+    let meta = envelope::parse_meta(&response)
+        .remove(&uid)
+        .unwrap_or_default();
+    Ok(envelope::build_task(uid, headers, meta))
+}
+
+// Messages can't be changed, so this appends a modified copy and deletes
+// the original. The copy is found by its Message-ID to give it the flags
+// of the original, like its priority, and that Message-ID is returned.
+// Messages without one get a new one.
+fn replace_task<T, F>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+    change: F,
+) -> Result<String>
+where
+    T: Read + Write,
+    F: FnOnce(&Task, &[u8]) -> Result<Vec<u8>>,
+{
+    let task = fetch_task(imap, uid)?;
+    let raw = fetch_raw(imap, uid)?;
+    let mut copy = change(&task, &raw)?;
+    // Or there'd be no finding the copy
+    let message_id = if task.message_id.is_empty() {
+        let id = envelope::message_id();
+        let header = format!("<{}>", id);
+        copy = envelope::set_raw_header(&copy, "Message-ID", &header)?;
+        id
+    } else {
+        task.message_id.clone()
+    };
+    imap.append(folder, &copy)?;
+    delete_task(imap, uid);
+    copy_flags(imap, &task, &message_id);
+    Ok(message_id)
+}
+
+// Gives the flags of a task to the copy of it with that Message-ID
+fn copy_flags<T: Read + Write>(
+    imap: &mut Session<T>,
+    task: &Task,
+    message_id: &str,
+) {
+    let flags: Vec<&str> = task.flags
+        .iter()
+        .filter(|f| *f != "\\Recent" && *f != "\\Deleted")
+        .map(|f| &f[..])
+        .collect();
+    if flags.is_empty() || message_id.is_empty() {
+        return;
+    }
+    match find_by_id(imap, message_id) {
+        Ok(Some(copy)) => {
+            let flags = format!("+FLAGS ({})", flags.join(" "));
+            if let Err(e) = imap.uid_store(&copy.to_string(), &flags) {
+                warn!("Couldn't set the flags of the new {}: {}", copy, e);
+            }
+        }
+        Ok(None) => warn!("Couldn't find the new copy of {}", task.uid),
+        Err(e) => {
+            warn!("Couldn't look for the new copy of {}: {}", task.uid, e)
+        }
+    }
+}
+
+// The last message with that Message-ID in the selected folder
+fn find_by_id<T: Read + Write>(
+    imap: &mut Session<T>,
+    message_id: &str,
+) -> Result<Option<u64>> {
+    let id = search::quote(&format!("<{}>", message_id))
+        .ok_or("Can't search for that Message-ID")?;
+    let command = format!("UID SEARCH HEADER Message-ID {}", id);
+    let raw = imap.run_command_and_read_response(&command)?;
+    let uids = search::parse_response(&String::from_utf8_lossy(&raw));
+    Ok(uids.into_iter().max())
+}
+
+pub fn edit_task<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+    title: &str,
+) -> Result<()> {
+    replace_task(imap, folder, uid, |_, raw| {
+        envelope::set_header(raw, "Subject", title)
+    })?;
+    Ok(())
+}
+
+// The X-Due header overrides any date in the subject, so "none" removes it
+pub fn set_due<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+    date: Option<DateTime<FixedOffset>>,
+) -> Result<()> {
+    replace_task(imap, folder, uid, |_, raw| {
+        envelope::set_header(raw, "X-Due", &due::to_header(date))
+    })?;
+    Ok(())
+}
+
+// Clears whatever priority the task had before setting the new one. The
+// $Priority keywords need a folder that takes them, or else it's kept in
+// a header, which replaces the task, and then it returns the Message-ID
+// the copy kept. So does taking that header out once it's not needed.
+pub fn set_priority<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+    priority: Priority,
+) -> Result<Option<String>> {
+    let mbox = imap.select(folder)?;
+    let task = fetch_task(imap, uid)?;
+    let old = format!("-FLAGS ({})", priority::FLAGS.join(" "));
+    imap.uid_store(&uid.to_string(), &old)?;
+
+    let flag = priority.flag();
+    let permanent = flag.map_or(true, |flag| {
+        flag.starts_with('\\')
+            || mbox.permanent_flags.iter().any(|f| {
+                f == "\\*" || f.eq_ignore_ascii_case(flag)
+            })
+    });
+    if !permanent {
+        let header = priority::HEADER;
+        info!("{} takes no new keywords, using {} instead", folder, header);
+        let id = replace_task(imap, folder, uid, |_, raw| {
+            envelope::set_header(raw, priority::HEADER, priority.id())
+        })?;
+        return Ok(Some(id));
+    }
+
+    if let Some(flag) = flag {
+        imap.uid_store(&uid.to_string(), &format!("+FLAGS ({})", flag))?;
+    }
+    if task.priority_header.is_none() {
+        return Ok(None);
+    }
+    let id = replace_task(imap, folder, uid, |_, raw| {
+        Ok(envelope::remove_header(raw, priority::HEADER))
+    })?;
+    Ok(Some(id))
+}
+
+// A keyword when the folder takes new ones, or else a hashtag in the
+// subject, which replaces the task. Then it returns the Message-ID the
+// copy kept.
+pub fn add_tag<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+    tag: &str,
+) -> Result<Option<String>> {
+    let mbox = imap.select(folder)?;
+    let keywords = mbox.permanent_flags.iter().any(|f| f == "\\*");
+    if keywords && tags::is_keyword(tag) {
+        imap.uid_store(&uid.to_string(), &format!("+FLAGS ({})", tag))?;
+        return Ok(None);
+    }
+    if !tags::is_hashtag(tag) {
+        return Err(format!("'{}' can't be a keyword or a hashtag", tag).into());
+    }
+
+    info!("{} takes no new keywords, adding #{} to the subject", folder, tag);
+    let id = replace_task(imap, folder, uid, |task, raw| {
+        let title = tags::add_hashtag(&task.title, tag);
+        envelope::set_header(raw, "Subject", &title)
+    })?;
+    Ok(Some(id))
+}
+
+// From wherever the tag comes from: the keywords, the Gmail labels or the
+// subject, which replaces the task. Then it returns the Message-ID the
+// copy kept.
+pub fn remove_tag<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+    tag: &str,
+    gmail: bool,
+) -> Result<Option<String>> {
+    let task = fetch_task(imap, uid)?;
+    let lower = tag.to_lowercase();
+    let keywords: Vec<&str> = task.keywords()
+        .into_iter()
+        .filter(|k| k.to_lowercase() == lower)
+        .collect();
+    if !keywords.is_empty() {
+        let flags = format!("-FLAGS ({})", keywords.join(" "));
+        imap.uid_store(&uid.to_string(), &flags)?;
+    }
+    if gmail {
+        let fetch = fetch_items(imap, uid, "X-GM-LABELS")?;
+        let labels: Vec<String> = fetch
+            .get("X-GM-LABELS")
+            .map(|l| tags::labels(l.list()))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|l| l.to_lowercase() == lower)
+            .collect();
+        for label in labels {
+            let quoted = search::quote(&tags::encode_utf7(&label))
+                .ok_or_else(|| format!("Can't remove the label '{}'", label))?;
+            let labels = format!("-X-GM-LABELS ({})", quoted);
+            imap.uid_store(&uid.to_string(), &labels)?;
+        }
+    }
+
+    let hashtags = tags::hashtags(&task.title);
+    if !hashtags.iter().any(|h| h.to_lowercase() == lower) {
+        return Ok(None);
+    }
+    let title = tags::remove_hashtag(&task.title, tag);
+    let id = replace_task(imap, folder, uid, |_, raw| {
+        envelope::set_header(raw, "Subject", &title)
+    })?;
+    Ok(Some(id))
+}
+
+// Completed tasks are kept in the done folder, so they can be restored.
+// Without one they're just deleted. Recurring ones are added again first,
+// due on their next date, and then it returns the Message-ID of the copy.
+pub fn complete_task<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+    done: &str,
+) -> Result<Option<String>> {
+    let copy = recur(imap, folder, uid)?;
+    let archived = archive(imap, uid, done);
+    // Or the original stays along with its copy
+    if let (&Err(_), &Some(ref id)) = (&archived, &copy) {
+        match find_by_id(imap, id) {
+            Ok(Some(copy)) => delete_task(imap, copy),
+            Ok(None) => warn!("Couldn't find the new copy of {}", uid),
+            Err(e) => warn!("Couldn't take back the copy of {}: {}", uid, e),
+        }
+    }
+    archived?;
+    Ok(copy)
+}
+
+fn archive<T: Read + Write>(
+    imap: &mut Session<T>,
+    uid: u64,
+    done: &str,
+) -> Result<()> {
+    if !done.is_empty() {
+        let uid = uid.to_string();
+        if imap.uid_copy(&uid, done).is_err() {
+            info!("Creating folder {}", done);
+            let _ = imap.create(done);
+            imap.uid_copy(&uid, done)?;
+        }
+    }
+    delete_task(imap, uid);
+    Ok(())
+}
+
+// A fresh copy of a recurring task, with a Message-ID of its own and the
+// X-Recurred-From header pointing at the one completed, so restoring that
+// one can take it back. Rules in the subject are left there. It returns
+// the Message-ID of the copy, unless there was one already.
+fn recur<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    uid: u64,
+) -> Result<Option<String>> {
+    // Only the headers until it's known to recur, as the message may have
+    // big attachments
+    let task = fetch_task(imap, uid)?;
+    let rule = match task.recurrence() {
+        Some(rule) => rule,
+        None => return Ok(None),
+    };
+    if !recurred_from(imap, &task.message_id)?.is_empty() {
+        info!("'{}' was added again already", task.title);
+        return Ok(None);
+    }
+    let now = Local::now();
+    let (date, next) = match rule.next(task.due, now) {
+        Some(next) => next,
+        None => {
+            info!("'{}' doesn't recur anymore", task.title);
+            return Ok(None);
+        }
+    };
+
+    let raw = fetch_raw(imap, uid)?;
+
+    let id = envelope::message_id();
+    let header = format!("<{}>", id);
+    let mut mail = envelope::set_raw_header(&raw, "Message-ID", &header)?;
+    mail = envelope::set_header(&mail, "Date", &now.to_rfc2822())?;
+    mail = envelope::set_header(&mail, "X-Due", &due::to_header(Some(date)))?;
+    if Rule::from_subject(&task.title) != Some(rule) {
+        let value = format!("RRULE:{}", next);
+        mail = envelope::set_header(&mail, "X-Recurrence", &value)?;
+    }
+    if !task.message_id.is_empty() {
+        let from = format!("<{}>", task.message_id);
+        mail = envelope::set_raw_header(&mail, "X-Recurred-From", &from)?;
+    }
+    imap.append(folder, &mail)?;
+    copy_flags(imap, &task, &id);
+    info!("'{}' is due again {}", task.title, date);
+    Ok(Some(id))
+}
+
+// The copies recurring tasks with that Message-ID got in the selected
+// folder
+fn recurred_from<T: Read + Write>(
+    imap: &mut Session<T>,
+    message_id: &str,
+) -> Result<HashSet<u64>> {
+    if message_id.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let id = search::quote(&format!("<{}>", message_id))
+        .ok_or("Can't search for that Message-ID")?;
+    let command = format!("UID SEARCH HEADER X-Recurred-From {}", id);
+    let raw = imap.run_command_and_read_response(&command)?;
+    Ok(search::parse_response(&String::from_utf8_lossy(&raw)))
+}
+
+// Moves a task back from the done folder, found by its Message-ID
+pub fn restore_task<T: Read + Write>(
+    imap: &mut Session<T>,
+    folder: &str,
+    done: &str,
+    message_id: &str,
+) -> Result<()> {
+    if done.is_empty() {
+        return Err("There's no done folder to restore from".into());
+    }
+    if message_id.is_empty() {
+        return Err("Tasks without a Message-ID can't be restored".into());
+    }
+
+    imap.select(done)?;
+    let result: Result<()> = match find_by_id(imap, message_id) {
+        Ok(Some(uid)) => imap
+            .uid_copy(&uid.to_string(), folder)
+            .map(|_| delete_task(imap, uid))
+            .map_err(|e| e.into()),
+        Ok(None) => Err("Not in the done folder".into()),
+        Err(e) => Err(e),
+    };
+    imap.select(folder)?;
+    result?;
+
+    // Takes back the copy completing a recurring task added
+    for uid in recurred_from(imap, message_id)? {
+        delete_task(imap, uid);
+    }
+    Ok(())
+}
+
+// Pending tasks come from the folder, completed ones from the log the
+// window keeps of them. Then it returns when it was made.
+fn send_digest<T: Read + Write>(
+    imap: &mut Session<T>,
+    creds: &Creds,
+    folder: &str,
+    request: &Request,
+    gmail: bool,
+) -> Result<DateTime<Local>> {
+    let now = Local::now();
+    let tasks: Vec<Task> =
+        get_tasks(imap, folder, gmail)?.into_iter().collect();
+    let completed = digest::completed_since(request.since);
+    let (subject, text) =
+        digest::compose(&tasks, &completed, request.since, now);
+    let from = creds.from.as_ref().unwrap_or(&creds.user);
+
+    match request.via {
+        Via::Append(ref mailbox) => {
+            let mail = digest::message(from, from, &subject, &text, now);
+            imap.append(mailbox, &mail)?;
+        }
+        Via::Smtp(ref to) => {
+            let url = creds
+                .smtp_url
+                .as_ref()
+                .ok_or("There's no smtp_url in the config")?;
+            let server = smtp::parse_url(url)?;
+            let pass = creds.smtp_pass.as_ref().unwrap_or(&creds.pass);
+            let mail = digest::message(from, to, &subject, &text, now);
+            smtp::send(&server, pass, from, to, &mail)?;
+        }
+    }
+    info!("Sent the digest: {}", subject);
+    Ok(now)
+}
+
+pub fn add_task<T: Read + Write>(
+    imap: &mut Session<T>,
+    creds: &Creds,
+    folder: &str,
+    title: &str,
+    message_id: &str,
+) -> Result<()> {
+    let subject = envelope::encode_header(title)?;
+    let mail = format!(
+        "From: {user}\r\nTo: {user}\r\nSubject: {subject}\r\n\
+         Date: {date}\r\nMessage-ID: <{id}>\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\r\n",
+        user = creds.user,
+        subject = subject,
+        date = Local::now().to_rfc2822(),
+        id = message_id,
+    );
+    imap.append(folder, mail.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,127 @@
+// How urgent a task is, kept in the flags of its message: \Flagged, which
+// every mail client shows, is high, and the $Priority1 to $Priority3
+// keywords are high, medium and low. The highest one wins. Folders that
+// take no new keywords keep it in a header instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    None,
+    Low,
+    Medium,
+    High,
+}
+
+// The header keeping the priority where there are no $Priority keywords,
+// by its id, e.g. "X-Todo-Priority: medium"
+pub const HEADER: &'static str = "X-Todo-Priority";
+
+// Every flag a priority is kept in, to clear the old one when setting it
+pub const FLAGS: [&'static str; 4] =
+    ["\\Flagged", "$Priority1", "$Priority2", "$Priority3"];
+
+impl Default for Priority {
+    fn default() -> Priority {
+        Priority::None
+    }
+}
+
+impl Priority {
+    pub fn of(flags: &[String]) -> Priority {
+        flags
+            .iter()
+            .map(|f| match &f.to_lowercase()[..] {
+                "\\flagged" | "$priority1" => Priority::High,
+                "$priority2" => Priority::Medium,
+                "$priority3" => Priority::Low,
+                _ => Priority::None,
+            })
+            .max()
+            .unwrap_or_default()
+    }
+
+    // The flag setting it, \Flagged for high so mail clients show it too
+    pub fn flag(&self) -> Option<&'static str> {
+        match *self {
+            Priority::None => None,
+            Priority::Low => Some("$Priority3"),
+            Priority::Medium => Some("$Priority2"),
+            Priority::High => Some("\\Flagged"),
+        }
+    }
+
+    // The ids are the ones of the priority combo box in the UI
+    pub fn id(&self) -> &'static str {
+        match *self {
+            Priority::None => "none",
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Priority> {
+        match id {
+            "none" => Some(Priority::None),
+            "low" => Some(Priority::Low),
+            "medium" => Some(Priority::Medium),
+            "high" => Some(Priority::High),
+            _ => None,
+        }
+    }
+
+    // The one after it, going back to none after high
+    pub fn next(&self) -> Priority {
+        match *self {
+            Priority::None => Priority::Low,
+            Priority::Low => Priority::Medium,
+            Priority::Medium => Priority::High,
+            Priority::High => Priority::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn from_flags() {
+        assert_eq!(Priority::of(&[]), Priority::None);
+        assert_eq!(Priority::of(&flags(&["\\Seen", "work"])), Priority::None);
+        assert_eq!(Priority::of(&flags(&["\\Flagged"])), Priority::High);
+        assert_eq!(Priority::of(&flags(&["$priority2"])), Priority::Medium);
+        assert_eq!(Priority::of(&flags(&["$Priority3"])), Priority::Low);
+        // The highest one wins
+        let both = flags(&["$Priority3", "$Priority1", "$Priority2"]);
+        assert_eq!(Priority::of(&both), Priority::High);
+    }
+
+    #[test]
+    fn round_trip() {
+        let all =
+            [Priority::None, Priority::Low, Priority::Medium, Priority::High];
+        for priority in all.iter() {
+            assert_eq!(Priority::from_id(priority.id()), Some(*priority));
+            let flag = priority.flag().map(|f| flags(&[f])).unwrap_or_default();
+            assert_eq!(Priority::of(&flag), *priority);
+            assert!(priority.flag().map_or(true, |f| FLAGS.contains(&f)));
+        }
+        assert_eq!(Priority::from_id("urgent"), None);
+    }
+
+    #[test]
+    fn cycle() {
+        let mut priority = Priority::None;
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            priority = priority.next();
+            seen.push(priority);
+        }
+        let all = [Priority::Low, Priority::Medium, Priority::High];
+        assert_eq!(seen[..3], all);
+        assert_eq!(seen[3], Priority::None);
+    }
+}
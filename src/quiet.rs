@@ -0,0 +1,266 @@
+use chrono::{DateTime, Datelike, Local, NaiveTime};
+
+use due;
+use notifier::{self, Note};
+
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Seconds between checks of the desktop's do not disturb mode
+const DND_CHECK: u64 = 30;
+
+// When notifications are held back: every day between two times, which
+// may span midnight, and all day on some days of the week
+#[derive(Debug, Default)]
+pub struct Quiet {
+    hours: Option<(NaiveTime, NaiveTime)>,
+    // Days from Monday
+    days: Vec<u32>,
+    held: Vec<Note>,
+    // Held from the desktop notifiers only, while the desktop didn't want
+    // to be disturbed
+    held_desktop: Vec<Note>,
+}
+
+impl Quiet {
+    pub fn new(hours: Option<(NaiveTime, NaiveTime)>, days: Vec<u32>) -> Quiet {
+        Quiet {
+            hours: hours,
+            days: days,
+            held: Vec::new(),
+            held_desktop: Vec::new(),
+        }
+    }
+
+    pub fn is_quiet(&self, now: DateTime<Local>) -> bool {
+        if self.days.contains(&now.weekday().num_days_from_monday()) {
+            return true;
+        }
+        match self.hours {
+            Some((start, end)) if start <= end => {
+                now.time() >= start && now.time() < end
+            }
+            Some((start, end)) => now.time() >= start || now.time() < end,
+            None => false,
+        }
+    }
+
+    // The note, unless it's quiet now and it's held for later. While the
+    // desktop doesn't want to be disturbed, the desktop notifiers hold it
+    // for later, but the others get it anyway.
+    pub fn pass(
+        &mut self,
+        note: Note,
+        now: DateTime<Local>,
+        dnd: bool,
+    ) -> Option<Note> {
+        if self.is_quiet(now) {
+            debug!("Holding notification '{}'", note.summary);
+            self.held.push(note);
+            None
+        } else {
+            if dnd {
+                let summary = &note.summary;
+                debug!("Holding notification '{}' from the desktop", summary);
+                self.held_desktop.push(note.clone());
+            }
+            Some(note)
+        }
+    }
+
+    // Once it's not quiet anymore, what was held: the note itself if
+    // there's a single one, or a digest of them all. Desktop notifiers
+    // still hold it if the desktop doesn't want to be disturbed.
+    pub fn release(&mut self, now: DateTime<Local>, dnd: bool) -> Option<Note> {
+        if self.held.is_empty() || self.is_quiet(now) {
+            return None;
+        }
+        let held: Vec<Note> = self.held.drain(..).collect();
+        if dnd {
+            self.held_desktop.extend(held.iter().cloned());
+        }
+        gather(held)
+    }
+
+    // What the desktop notifiers held, once the desktop wants to be
+    // disturbed again
+    pub fn release_desktop(&mut self, dnd: bool) -> Option<Note> {
+        if dnd {
+            return None;
+        }
+        gather(self.held_desktop.drain(..).collect())
+    }
+
+    pub fn is_holding(&self) -> bool {
+        !self.held.is_empty() || !self.held_desktop.is_empty()
+    }
+}
+
+fn gather(mut held: Vec<Note>) -> Option<Note> {
+    match held.len() {
+        0 => None,
+        1 => held.pop(),
+        _ => Some(notifier::digest(&held)),
+    }
+}
+
+// e.g. "22:00-07:00"
+pub fn parse_hours(text: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let mut times = text.splitn(2, '-');
+    let start = NaiveTime::parse_from_str(times.next()?.trim(), "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(times.next()?.trim(), "%H:%M").ok()?;
+    Some((start, end))
+}
+
+// e.g. "sat,sun"
+pub fn parse_days(text: &str) -> Option<Vec<u32>> {
+    text.split(',')
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| due::weekday(&d.trim().to_lowercase()))
+        .collect()
+}
+
+// Whether the desktop is in do not disturb mode, as last checked from a
+// thread of its own, since asking takes running a command. Only GNOME
+// tells, through its "show-banners" setting, so elsewhere it's never on.
+pub fn watch_dnd() -> Arc<AtomicBool> {
+    let dnd = Arc::new(AtomicBool::new(false));
+    let watched = dnd.clone();
+    let result = thread::Builder::new()
+        .name("dnd".to_string())
+        .spawn(move || {
+            while let Some(on) = gnome_dnd() {
+                watched.store(on, Ordering::Relaxed);
+                thread::sleep(Duration::from_secs(DND_CHECK));
+            }
+            debug!("Not checking for do not disturb mode");
+        });
+    if let Err(e) = result {
+        error!("Couldn't start the do not disturb thread: {}", e);
+    }
+    dnd
+}
+
+// None when there's no GNOME setting to ask
+fn gnome_dnd() -> Option<bool> {
+    Command::new("gsettings")
+        .args(&["get", "org.gnome.desktop.notifications", "show-banners"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim() == "false")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use notifier::Kind;
+
+    // Wednesday 21 October 2026
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.ymd(2026, 10, day).and_hms(hour, minute, 0)
+    }
+
+    fn note(summary: &str) -> Note {
+        Note {
+            kind: Kind::Added,
+            summary: summary.to_string(),
+            body: String::new(),
+            task: None,
+        }
+    }
+
+    #[test]
+    fn quiet_hours() {
+        let quiet = Quiet::new(parse_hours("09:00-17:30"), Vec::new());
+        assert!(!quiet.is_quiet(at(21, 8, 59)));
+        assert!(quiet.is_quiet(at(21, 9, 0)));
+        assert!(quiet.is_quiet(at(21, 17, 29)));
+        assert!(!quiet.is_quiet(at(21, 17, 30)));
+
+        assert!(!Quiet::new(None, Vec::new()).is_quiet(at(21, 3, 0)));
+    }
+
+    #[test]
+    fn quiet_overnight() {
+        let quiet = Quiet::new(parse_hours("22:00-07:00"), Vec::new());
+        assert!(!quiet.is_quiet(at(21, 21, 59)));
+        assert!(quiet.is_quiet(at(21, 22, 0)));
+        assert!(quiet.is_quiet(at(21, 23, 59)));
+        assert!(quiet.is_quiet(at(22, 0, 0)));
+        assert!(quiet.is_quiet(at(22, 6, 59)));
+        assert!(!quiet.is_quiet(at(22, 7, 0)));
+        assert!(!quiet.is_quiet(at(22, 12, 0)));
+    }
+
+    #[test]
+    fn quiet_days() {
+        let days = parse_days("sat, Sunday").unwrap();
+        assert_eq!(days, vec![5, 6]);
+        let quiet = Quiet::new(parse_hours("22:00-07:00"), days);
+        assert!(!quiet.is_quiet(at(23, 12, 0)));
+        assert!(quiet.is_quiet(at(24, 0, 0)));
+        assert!(quiet.is_quiet(at(24, 12, 0)));
+        assert!(quiet.is_quiet(at(25, 23, 59)));
+        // Monday morning is still in the quiet hours
+        assert!(quiet.is_quiet(at(26, 6, 0)));
+        assert!(!quiet.is_quiet(at(26, 7, 0)));
+    }
+
+    #[test]
+    fn parse_settings() {
+        let hours = parse_hours(" 22:00 - 07:30 ").unwrap();
+        assert_eq!(hours.0, NaiveTime::from_hms(22, 0, 0));
+        assert_eq!(hours.1, NaiveTime::from_hms(7, 30, 0));
+        assert_eq!(parse_hours("22:00"), None);
+        assert_eq!(parse_hours("late-early"), None);
+        assert_eq!(parse_days(""), Some(Vec::new()));
+        assert_eq!(parse_days("mon,someday"), None);
+    }
+
+    #[test]
+    fn hold_and_release() {
+        let mut quiet = Quiet::new(parse_hours("22:00-07:00"), Vec::new());
+        let day = at(21, 12, 0);
+        let night = at(21, 23, 0);
+
+        assert!(quiet.pass(note("a"), day, false).is_some());
+        assert!(quiet.pass(note("a"), night, false).is_none());
+        assert!(quiet.is_holding());
+        assert!(quiet.release(night, false).is_none());
+        // A single one comes back as it was
+        assert_eq!(quiet.release(at(22, 7, 0), false).unwrap().summary, "a");
+        assert!(!quiet.is_holding());
+        assert!(quiet.release(at(22, 7, 0), false).is_none());
+
+        for summary in &["a", "b", "c"] {
+            quiet.pass(note(summary), night, false);
+        }
+        let digest = quiet.release(day, false).unwrap();
+        assert_eq!(digest.kind, Kind::Digest);
+        assert_eq!(digest.body, "a\nb\nc");
+    }
+
+    #[test]
+    fn hold_desktop() {
+        let mut quiet = Quiet::new(parse_hours("22:00-07:00"), Vec::new());
+        let day = at(21, 12, 0);
+
+        // The others get it, the desktop only later
+        assert!(quiet.pass(note("a"), day, true).is_some());
+        assert!(quiet.release_desktop(true).is_none());
+        quiet.pass(note("b"), at(21, 23, 0), true);
+        assert!(quiet.release(day, true).is_some());
+        assert!(quiet.is_holding());
+
+        let digest = quiet.release_desktop(false).unwrap();
+        assert_eq!(digest.body, "a\nb");
+        assert!(!quiet.is_holding());
+        assert!(quiet.release_desktop(false).is_none());
+    }
+}
@@ -0,0 +1,553 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate,
+             NaiveDateTime, NaiveTime, TimeZone};
+
+use due;
+
+use std::fmt;
+
+// Recurring tasks have a rule in an X-Recurrence header, in the RRULE
+// syntax of iCalendar ("RRULE:FREQ=MONTHLY"), or else in the subject:
+// "Backup NAS every month", "Water the plants every other day". Completing
+// one adds a copy due on the next date the rule gives.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+// The part of RRULE that makes sense for chores: FREQ, INTERVAL, BYDAY
+// (without ordinals, for daily and weekly rules), BYMONTHDAY (a single
+// day, for monthly ones), COUNT and UNTIL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub freq: Freq,
+    pub interval: u32,
+    // Days from Monday
+    pub by_day: Vec<u32>,
+    pub by_month_day: Option<u32>,
+    // How many times it's left to happen, counting the task with the rule
+    pub count: Option<u32>,
+    pub until: Option<NaiveDate>,
+}
+
+const DAYS: [&'static str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+impl Rule {
+    fn new(freq: Freq) -> Rule {
+        Rule {
+            freq: freq,
+            interval: 1,
+            by_day: Vec::new(),
+            by_month_day: None,
+            count: None,
+            until: None,
+        }
+    }
+
+    // The value of an X-Recurrence header, with or without "RRULE:". Parts
+    // it doesn't know make it None rather than a rule that recurs wrongly.
+    pub fn parse(value: &str) -> Option<Rule> {
+        let value = value.trim();
+        let value = if value.to_uppercase().starts_with("RRULE:") {
+            &value[6..]
+        } else {
+            value
+        };
+
+        let mut freq = None;
+        let mut rule = Rule::new(Freq::Daily);
+        for part in value.split(';').filter(|p| !p.trim().is_empty()) {
+            let mut pair = part.splitn(2, '=');
+            let name = pair.next()?.trim().to_uppercase();
+            let value = pair.next()?.trim().to_uppercase();
+            match &name[..] {
+                "FREQ" => {
+                    freq = Some(match &value[..] {
+                        "DAILY" => Freq::Daily,
+                        "WEEKLY" => Freq::Weekly,
+                        "MONTHLY" => Freq::Monthly,
+                        "YEARLY" => Freq::Yearly,
+                        _ => return None,
+                    })
+                }
+                "INTERVAL" => match value.parse() {
+                    Ok(0) | Err(_) => return None,
+                    Ok(interval) => rule.interval = interval,
+                },
+                "BYDAY" => for day in value.split(',') {
+                    let day = DAYS.iter().position(|d| *d == day)? as u32;
+                    if !rule.by_day.contains(&day) {
+                        rule.by_day.push(day);
+                    }
+                },
+                "BYMONTHDAY" => match value.parse() {
+                    Ok(day) if day >= 1 && day <= 31 => {
+                        rule.by_month_day = Some(day)
+                    }
+                    _ => return None,
+                },
+                "COUNT" => match value.parse() {
+                    Ok(0) | Err(_) => return None,
+                    Ok(count) => rule.count = Some(count),
+                },
+                // Only the date of a date-time, which is all due dates need
+                "UNTIL" => {
+                    let date = value.get(..8)?;
+                    rule.until =
+                        Some(NaiveDate::parse_from_str(date, "%Y%m%d").ok()?)
+                }
+                "WKST" if value == "MO" => (),
+                _ => return None,
+            }
+        }
+        rule.freq = freq?;
+        rule.by_day.sort();
+
+        let days_fit = match rule.freq {
+            Freq::Daily | Freq::Weekly => true,
+            Freq::Monthly | Freq::Yearly => rule.by_day.is_empty(),
+        };
+        let month_day_fits =
+            rule.by_month_day.is_none() || rule.freq == Freq::Monthly;
+        if days_fit && month_day_fits {
+            Some(rule)
+        } else {
+            None
+        }
+    }
+
+    // "every month", "every 2 weeks", "every other day", "every weekday",
+    // "every friday"
+    pub fn from_subject(subject: &str) -> Option<Rule> {
+        let words: Vec<String> = subject
+            .split_whitespace()
+            .map(|w| {
+                w.trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase()
+            })
+            .collect();
+        words
+            .iter()
+            .enumerate()
+            .filter(|&(_, w)| w == "every")
+            .filter_map(|(i, _)| {
+                let (interval, unit) = match words.get(i + 1) {
+                    Some(w) if w == "other" => (2, words.get(i + 2)?),
+                    Some(w) => match w.parse() {
+                        Ok(0) => return None,
+                        Ok(n) => (n, words.get(i + 2)?),
+                        Err(_) => (1, w),
+                    },
+                    None => return None,
+                };
+                let unit = if interval > 1 {
+                    unit.trim_right_matches('s')
+                } else {
+                    &unit[..]
+                };
+                let mut rule = match unit {
+                    "day" => Rule::new(Freq::Daily),
+                    "week" => Rule::new(Freq::Weekly),
+                    "month" => Rule::new(Freq::Monthly),
+                    "year" => Rule::new(Freq::Yearly),
+                    "weekday" if interval == 1 => {
+                        let mut rule = Rule::new(Freq::Daily);
+                        rule.by_day = (0..5).collect();
+                        rule
+                    }
+                    day => {
+                        let mut rule = Rule::new(Freq::Weekly);
+                        rule.by_day = vec![due::weekday(day)?];
+                        rule
+                    }
+                };
+                rule.interval = interval;
+                Some(rule)
+            })
+            .next()
+    }
+
+    // The next due date, after the current one or else after today, along
+    // with the rule for the task due then. Dates that went by while the
+    // task was overdue are skipped, and don't count for COUNT.
+    pub fn next(
+        &self,
+        due: Option<DateTime<FixedOffset>>,
+        now: DateTime<Local>,
+    ) -> Option<(DateTime<FixedOffset>, Rule)> {
+        if self.count.map_or(false, |c| c <= 1) {
+            return None;
+        }
+        let hour = NaiveTime::from_hms(::DUE_HOUR, 0, 0);
+        let start = match due {
+            Some(due) => due.with_timezone(&Local).naive_local(),
+            None => now.date().naive_local().and_time(hour),
+        };
+
+        // Monthly rules keep the day of the month they started on, so a
+        // task due on the 31st is back on the 31st after February
+        let mut rule = self.clone();
+        if rule.freq == Freq::Monthly && rule.by_month_day.is_none() {
+            rule.by_month_day = Some(start.day());
+        }
+        rule.count = self.count.map(|c| c - 1);
+
+        let mut date = start;
+        // Enough for a daily rule that's years overdue
+        for _ in 0..10000 {
+            date = rule.after(date)?;
+            if rule.until.map_or(false, |until| date.date() > until) {
+                return None;
+            }
+            let time = match Local.from_local_datetime(&date).earliest() {
+                Some(time) => time,
+                None => continue,
+            };
+            if time > now {
+                return Some((time.with_timezone(time.offset()), rule));
+            }
+        }
+        None
+    }
+
+    // The first date the rule gives after that one, at the same time
+    fn after(&self, date: NaiveDateTime) -> Option<NaiveDateTime> {
+        let day = date.date();
+        let next = match self.freq {
+            Freq::Daily => {
+                let step = Duration::days(self.interval as i64);
+                // Days of the week come back within a week of steps, or
+                // never
+                (1..8)
+                    .map(|i| day + step * i)
+                    .find(|d| self.on_day(d))?
+            }
+            Freq::Weekly => {
+                let days = if self.by_day.is_empty() {
+                    vec![day.weekday().num_days_from_monday()]
+                } else {
+                    self.by_day.clone()
+                };
+                let weekday = day.weekday().num_days_from_monday();
+                match days.iter().find(|&&d| d > weekday) {
+                    // Later on in the same week
+                    Some(&d) => day + Duration::days((d - weekday) as i64),
+                    None => {
+                        let monday = day - Duration::days(weekday as i64)
+                            + Duration::weeks(self.interval as i64);
+                        monday + Duration::days(days[0] as i64)
+                    }
+                }
+            }
+            Freq::Monthly => {
+                let wanted = self.by_month_day.unwrap_or(day.day());
+                let this = clamp(day.year(), day.month(), wanted)?;
+                if this > day {
+                    this
+                } else {
+                    let months = day.month0() + self.interval;
+                    let year = day.year() + (months / 12) as i32;
+                    clamp(year, months % 12 + 1, wanted)?
+                }
+            }
+            Freq::Yearly => clamp(
+                day.year() + self.interval as i32,
+                day.month(),
+                day.day(),
+            )?,
+        };
+        Some(next.and_time(date.time()))
+    }
+
+    fn on_day(&self, day: &NaiveDate) -> bool {
+        self.by_day.is_empty()
+            || self.by_day
+                .contains(&day.weekday().num_days_from_monday())
+    }
+
+    // e.g. "every month", "every 2 weeks on Mon, Thu", "every weekday"
+    pub fn describe(&self) -> String {
+        if self.freq == Freq::Daily
+            && self.interval == 1
+            && self.by_day == vec![0, 1, 2, 3, 4]
+        {
+            return String::from("every weekday");
+        }
+        let unit = match self.freq {
+            Freq::Daily => "day",
+            Freq::Weekly => "week",
+            Freq::Monthly => "month",
+            Freq::Yearly => "year",
+        };
+        let mut text = match self.interval {
+            1 => format!("every {}", unit),
+            n => format!("every {} {}s", n, unit),
+        };
+        if !self.by_day.is_empty() {
+            let names = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+            let days: Vec<&str> =
+                self.by_day.iter().map(|&d| names[d as usize]).collect();
+            text.push_str(&format!(" on {}", days.join(", ")));
+        }
+        if let Some(day) = self.by_month_day {
+            text.push_str(&format!(" on the {}", ordinal(day)));
+        }
+        text
+    }
+}
+
+// The RRULE, without the "RRULE:"
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let freq = match self.freq {
+            Freq::Daily => "DAILY",
+            Freq::Weekly => "WEEKLY",
+            Freq::Monthly => "MONTHLY",
+            Freq::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", freq)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<&str> =
+                self.by_day.iter().map(|&d| DAYS[d as usize]).collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if let Some(day) = self.by_month_day {
+            write!(f, ";BYMONTHDAY={}", day)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%d"))?;
+        }
+        Ok(())
+    }
+}
+
+// That day of the month, or its last day if it's shorter
+fn clamp(year: i32, month: u32, day: u32) -> Option<NaiveDate> {
+    (28..day + 1)
+        .rev()
+        .filter_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+        .next()
+        .or_else(|| NaiveDate::from_ymd_opt(year, month, day))
+}
+
+fn ordinal(n: u32) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11...13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn due(year: i32, month: u32, day: u32) -> DateTime<FixedOffset> {
+        let time = Local.ymd(year, month, day).and_hms(::DUE_HOUR, 0, 0);
+        time.with_timezone(time.offset())
+    }
+
+    // Saturday 10 January 2026, at noon
+    fn now() -> DateTime<Local> {
+        Local.ymd(2026, 1, 10).and_hms(12, 0, 0)
+    }
+
+    // The next due day and the rule for it
+    fn next(
+        rule: &Rule,
+        due: DateTime<FixedOffset>,
+    ) -> Option<(NaiveDate, Rule)> {
+        rule.next(Some(due), now()).map(|(date, rule)| {
+            (date.with_timezone(&Local).date().naive_local(), rule)
+        })
+    }
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(year, month, day)
+    }
+
+    #[test]
+    fn parse_rrule() {
+        let rule =
+            Rule::parse("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=TH,MO").unwrap();
+        assert_eq!(rule.freq, Freq::Weekly);
+        assert_eq!(rule.interval, 2);
+        assert_eq!(rule.by_day, vec![0, 3]);
+        assert_eq!(rule.to_string(), "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH");
+
+        let rule = Rule::parse(" freq=monthly;bymonthday=15;count=3 ").unwrap();
+        assert_eq!(rule.by_month_day, Some(15));
+        assert_eq!(rule.count, Some(3));
+        assert_eq!(Rule::parse(&rule.to_string()), Some(rule));
+    }
+
+    #[test]
+    fn parse_until() {
+        let rule = Rule::parse("FREQ=DAILY;UNTIL=20261231T235959Z").unwrap();
+        assert_eq!(rule.until, Some(day(2026, 12, 31)));
+        let rule = Rule::parse("FREQ=DAILY;UNTIL=20261231").unwrap();
+        assert_eq!(rule.until, Some(day(2026, 12, 31)));
+        assert_eq!(Rule::parse("FREQ=DAILY;UNTIL=2026"), None);
+        assert_eq!(Rule::parse("FREQ=DAILY;UNTIL=20261350"), None);
+    }
+
+    #[test]
+    fn parse_rejects_what_it_cant_follow() {
+        for rule in &[
+            "",
+            "RRULE:",
+            "INTERVAL=2",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;BYSETPOS=1",
+            "FREQ=DAILY;BYHOUR=9",
+            "FREQ=WEEKLY;WKST=SU",
+            "FREQ=MONTHLY;BYDAY=1MO",
+            "FREQ=WEEKLY;BYDAY=-1FR",
+            "FREQ=YEARLY;BYDAY=MO",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=MONTHLY;BYMONTHDAY=-1",
+            "FREQ=DAILY;COUNT=0",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;INTERVAL=x",
+            "FREQ",
+        ] {
+            assert_eq!(Rule::parse(rule), None, "{}", rule);
+        }
+    }
+
+    #[test]
+    fn next_keeps_the_end_of_the_month() {
+        let rule = Rule::parse("FREQ=MONTHLY").unwrap();
+        let (date, rule) = next(&rule, due(2026, 1, 31)).unwrap();
+        assert_eq!(date, day(2026, 2, 28));
+        assert_eq!(rule.to_string(), "FREQ=MONTHLY;BYMONTHDAY=31");
+        let (date, _) = next(&rule, due(2026, 2, 28)).unwrap();
+        assert_eq!(date, day(2026, 3, 31));
+
+        let rule = Rule::parse("FREQ=MONTHLY;BYMONTHDAY=20").unwrap();
+        let (date, _) = next(&rule, due(2026, 1, 12)).unwrap();
+        assert_eq!(date, day(2026, 1, 20));
+    }
+
+    #[test]
+    fn next_with_interval_and_days() {
+        let rule = Rule::parse("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH").unwrap();
+        // Later in the same week, and then two weeks on
+        let (date, _) = next(&rule, due(2026, 1, 12)).unwrap();
+        assert_eq!(date, day(2026, 1, 15));
+        let (date, _) = next(&rule, due(2026, 1, 15)).unwrap();
+        assert_eq!(date, day(2026, 1, 26));
+
+        let rule = Rule::parse("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR").unwrap();
+        let (date, _) = next(&rule, due(2026, 1, 16)).unwrap();
+        assert_eq!(date, day(2026, 1, 19));
+
+        let rule = Rule::parse("FREQ=DAILY;INTERVAL=3").unwrap();
+        let (date, _) = next(&rule, due(2026, 1, 11)).unwrap();
+        assert_eq!(date, day(2026, 1, 14));
+
+        // Steps of a week never land on another day
+        let rule = Rule::parse("FREQ=DAILY;INTERVAL=7;BYDAY=MO").unwrap();
+        assert_eq!(next(&rule, due(2026, 1, 11)), None);
+    }
+
+    #[test]
+    fn next_skips_dates_gone_by() {
+        let rule = Rule::parse("FREQ=DAILY;COUNT=3").unwrap();
+        let (date, rule) = next(&rule, due(2025, 12, 1)).unwrap();
+        assert_eq!(date, day(2026, 1, 11));
+        assert_eq!(rule.count, Some(2));
+
+        let rule = Rule::parse("FREQ=WEEKLY").unwrap();
+        let (date, _) = next(&rule, due(2025, 12, 1)).unwrap();
+        assert_eq!(date, day(2026, 1, 12));
+
+        let rule = Rule::parse("FREQ=YEARLY").unwrap();
+        let (date, _) = next(&rule, due(2024, 2, 29)).unwrap();
+        assert_eq!(date, day(2026, 2, 28));
+    }
+
+    #[test]
+    fn next_without_a_due_date() {
+        let rule = Rule::parse("FREQ=WEEKLY").unwrap();
+        let (date, _) = rule.next(None, now()).unwrap();
+        assert_eq!(date, due(2026, 1, 17));
+    }
+
+    #[test]
+    fn next_until_the_count_runs_out() {
+        let rule = Rule::parse("FREQ=DAILY;COUNT=2").unwrap();
+        let (date, rule) = next(&rule, due(2026, 1, 11)).unwrap();
+        assert_eq!(date, day(2026, 1, 12));
+        assert_eq!(rule.to_string(), "FREQ=DAILY;COUNT=1");
+        assert_eq!(next(&rule, due(2026, 1, 12)), None);
+    }
+
+    #[test]
+    fn next_until_the_end_date() {
+        let rule = Rule::parse("FREQ=DAILY;UNTIL=20260112").unwrap();
+        let (date, rule) = next(&rule, due(2026, 1, 11)).unwrap();
+        assert_eq!(date, day(2026, 1, 12));
+        assert_eq!(next(&rule, due(2026, 1, 12)), None);
+
+        // Nor after skipping the dates gone by
+        let rule = Rule::parse("FREQ=DAILY;UNTIL=20260105").unwrap();
+        assert_eq!(next(&rule, due(2026, 1, 1)), None);
+    }
+
+    #[test]
+    fn from_subject() {
+        let rule = |subject| Rule::from_subject(subject).map(|r| r.to_string());
+        let some = |rule: &str| Some(rule.to_string());
+        assert_eq!(rule("Backup NAS every month"), some("FREQ=MONTHLY"));
+        assert_eq!(
+            rule("Water the plants every other day"),
+            some("FREQ=DAILY;INTERVAL=2")
+        );
+        assert_eq!(
+            rule("Status report every 2 weeks"),
+            some("FREQ=WEEKLY;INTERVAL=2")
+        );
+        assert_eq!(rule("Gym every Friday."), some("FREQ=WEEKLY;BYDAY=FR"));
+        assert_eq!(
+            rule("Standup every weekday"),
+            some("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR")
+        );
+        assert_eq!(rule("Taxes every year @2026-04-30"), some("FREQ=YEARLY"));
+        assert_eq!(rule("Everything at once"), None);
+        assert_eq!(rule("Read every book"), None);
+        assert_eq!(rule("Every 0 days"), None);
+        assert_eq!(rule("Call every"), None);
+    }
+
+    #[test]
+    fn describe() {
+        let describe = |rule| Rule::parse(rule).unwrap().describe();
+        assert_eq!(describe("FREQ=MONTHLY"), "every month");
+        assert_eq!(
+            describe("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH"),
+            "every 2 weeks on Mon, Thu"
+        );
+        assert_eq!(
+            describe("FREQ=MONTHLY;BYMONTHDAY=22"),
+            "every month on the 22nd"
+        );
+        assert_eq!(
+            describe("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR"),
+            "every weekday"
+        );
+    }
+}
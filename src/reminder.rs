@@ -0,0 +1,320 @@
+use chrono::{DateTime, Duration, FixedOffset, Local};
+
+use {due, Task};
+
+use std::collections::HashMap;
+
+// Where the scheduler gets the time from, so it can be driven by hand
+pub trait Clock {
+    fn now(&self) -> DateTime<Local>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reminder {
+    pub uid: u64,
+    pub title: String,
+    pub due: Option<DateTime<FixedOffset>>,
+    // e.g. "due today 17:00" or "overdue since Mon 27 Oct"
+    pub text: String,
+}
+
+// Decides when each task with a due date is reminded of: at every lead
+// time before it's due, when it's due, and then every "nag" while it's
+// overdue. Only the latest of those moments that has passed counts, so
+// starting up late doesn't bring a burst of stale reminders.
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    leads: Vec<Duration>,
+    nag: Option<Duration>,
+    // When each task was last reminded of, and the due date it was for, so
+    // changing the due date starts over
+    sent: HashMap<u64, (DateTime<FixedOffset>, DateTime<Local>)>,
+    snoozed: HashMap<u64, DateTime<Local>>,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(clock: C, leads: Vec<Duration>, nag: Option<Duration>) -> Self {
+        Scheduler {
+            clock: clock,
+            leads: leads,
+            nag: nag,
+            sent: HashMap::new(),
+            snoozed: HashMap::new(),
+        }
+    }
+
+    // The reminders due now, which are taken as sent
+    pub fn check<'a, I>(&mut self, tasks: I) -> Vec<Reminder>
+    where
+        I: IntoIterator<Item = &'a Task>,
+    {
+        let now = self.clock.now();
+        let tasks: Vec<&Task> = tasks.into_iter().collect();
+        self.sent.retain(|uid, _| tasks.iter().any(|t| t.uid == *uid));
+        self.snoozed.retain(|uid, _| tasks.iter().any(|t| t.uid == *uid));
+
+        let mut reminders = Vec::new();
+        for task in tasks {
+            let fire = match self.snoozed.get(&task.uid).cloned() {
+                Some(until) if until > now => false,
+                Some(_) => {
+                    self.snoozed.remove(&task.uid);
+                    true
+                }
+                None => match task.due {
+                    Some(due) => match self.last_moment(due, now) {
+                        Some(moment) => match self.sent.get(&task.uid) {
+                            Some(&(d, at)) => d != due || at < moment,
+                            None => true,
+                        },
+                        None => false,
+                    },
+                    None => false,
+                },
+            };
+            if fire {
+                if let Some(due) = task.due {
+                    self.sent.insert(task.uid, (due, now));
+                }
+                reminders.push(Reminder {
+                    uid: task.uid,
+                    title: task.title.clone(),
+                    due: task.due,
+                    text: task.due.map_or_else(
+                        || String::from("Snoozed task"),
+                        |due| due::describe(due, now),
+                    ),
+                });
+            }
+        }
+        reminders
+    }
+
+    // No reminders for the task until then, and one right after, even if
+    // it has no due date
+    pub fn snooze(&mut self, uid: u64, duration: Duration) {
+        let until = self.clock.now() + duration;
+        self.snoozed.insert(uid, until);
+    }
+
+    fn last_moment(
+        &self,
+        due: DateTime<FixedOffset>,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        let due = due.with_timezone(&Local);
+        if now >= due {
+            return Some(match self.nag {
+                Some(nag) if nag > Duration::zero() => {
+                    let nags = (now - due).num_seconds() / nag.num_seconds();
+                    due + Duration::seconds(nags * nag.num_seconds())
+                }
+                _ => due,
+            });
+        }
+        self.leads.iter().map(|&lead| due - lead).filter(|&m| m <= now).max()
+    }
+}
+
+// Lead times or intervals like "90m", "1h" or "2d", with "0" for none
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    if text == "0" {
+        return Some(Duration::zero());
+    }
+    let (number, unit) = text.split_at(text.char_indices().last()?.0);
+    let number: i64 = number.parse().ok()?;
+    match unit {
+        "m" => Some(Duration::minutes(number)),
+        "h" => Some(Duration::hours(number)),
+        "d" => Some(Duration::days(number)),
+        _ => None,
+    }
+}
+
+// A comma separated list of lead times, e.g. "1d,1h"
+pub fn parse_leads(text: &str) -> Option<Vec<Duration>> {
+    text.split(',')
+        .filter(|l| !l.trim().is_empty())
+        .map(parse_duration)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Set by hand from the tests
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<DateTime<Local>>>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> DateTime<Local> {
+            self.0.get()
+        }
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.ymd(2026, 10, day).and_hms(hour, minute, 0)
+    }
+
+    fn task(uid: u64, due: Option<DateTime<Local>>) -> Task {
+        Task {
+            title: format!("Task {}", uid),
+            uid: uid,
+            message_id: String::new(),
+            from_name: String::new(),
+            from_address: String::new(),
+            date: None,
+            received: None,
+            flags: Vec::new(),
+            tags: Vec::new(),
+            in_reply_to: String::new(),
+            references: Vec::new(),
+            parent: None,
+            size: 0,
+            due: due.map(|d| d.with_timezone(d.offset())),
+            recurrence: None,
+            priority_header: None,
+        }
+    }
+
+    fn scheduler(
+        leads: &str,
+        nag: &str,
+    ) -> (Scheduler<FakeClock>, Rc<Cell<DateTime<Local>>>) {
+        let now = Rc::new(Cell::new(at(1, 0, 0)));
+        let nag = parse_duration(nag).unwrap();
+        let nag = if nag == Duration::zero() { None } else { Some(nag) };
+        let scheduler = Scheduler::new(
+            FakeClock(now.clone()),
+            parse_leads(leads).unwrap(),
+            nag,
+        );
+        (scheduler, now)
+    }
+
+    // The UIDs of the tasks reminded of at that time
+    fn check(
+        scheduler: &mut Scheduler<FakeClock>,
+        now: &Cell<DateTime<Local>>,
+        time: DateTime<Local>,
+        tasks: &[Task],
+    ) -> Vec<u64> {
+        now.set(time);
+        scheduler.check(tasks).into_iter().map(|r| r.uid).collect()
+    }
+
+    #[test]
+    fn lead_times() {
+        let (mut s, now) = scheduler("1d,1h", "0");
+        let tasks = vec![task(1, Some(at(21, 17, 0))), task(2, None)];
+        assert!(check(&mut s, &now, at(20, 12, 0), &tasks).is_empty());
+        assert_eq!(check(&mut s, &now, at(20, 17, 0), &tasks), vec![1]);
+        assert!(check(&mut s, &now, at(21, 15, 59), &tasks).is_empty());
+        assert_eq!(check(&mut s, &now, at(21, 16, 0), &tasks), vec![1]);
+        assert_eq!(check(&mut s, &now, at(21, 17, 0), &tasks), vec![1]);
+        // Just once when overdue without a nag
+        assert!(check(&mut s, &now, at(22, 17, 0), &tasks).is_empty());
+    }
+
+    #[test]
+    fn not_twice() {
+        let (mut s, now) = scheduler("1h", "1h");
+        let tasks = vec![task(1, Some(at(21, 17, 0)))];
+        assert_eq!(check(&mut s, &now, at(21, 16, 10), &tasks), vec![1]);
+        assert!(check(&mut s, &now, at(21, 16, 10), &tasks).is_empty());
+        assert!(check(&mut s, &now, at(21, 16, 50), &tasks).is_empty());
+    }
+
+    #[test]
+    fn starting_late() {
+        // Only the last moment that went by, not every one of them
+        let (mut s, now) = scheduler("1d,1h", "1h");
+        let tasks = vec![task(1, Some(at(21, 17, 0)))];
+        assert_eq!(check(&mut s, &now, at(21, 20, 10), &tasks), vec![1]);
+        assert!(check(&mut s, &now, at(21, 20, 50), &tasks).is_empty());
+        assert_eq!(check(&mut s, &now, at(21, 21, 0), &tasks), vec![1]);
+    }
+
+    #[test]
+    fn nag() {
+        let (mut s, now) = scheduler("", "90m");
+        let tasks = vec![task(1, Some(at(21, 17, 0)))];
+        assert!(check(&mut s, &now, at(21, 16, 59), &tasks).is_empty());
+        assert_eq!(check(&mut s, &now, at(21, 17, 0), &tasks), vec![1]);
+        assert!(check(&mut s, &now, at(21, 18, 29), &tasks).is_empty());
+        assert_eq!(check(&mut s, &now, at(21, 18, 30), &tasks), vec![1]);
+        assert_eq!(check(&mut s, &now, at(21, 20, 0), &tasks), vec![1]);
+    }
+
+    #[test]
+    fn new_due_date() {
+        let (mut s, now) = scheduler("1d", "0");
+        let mut tasks = vec![task(1, Some(at(21, 17, 0)))];
+        assert_eq!(check(&mut s, &now, at(21, 17, 5), &tasks), vec![1]);
+        // Put off to tomorrow, which is a day away already
+        tasks[0] = task(1, Some(at(22, 17, 0)));
+        assert_eq!(check(&mut s, &now, at(21, 17, 10), &tasks), vec![1]);
+        assert!(check(&mut s, &now, at(21, 17, 20), &tasks).is_empty());
+    }
+
+    #[test]
+    fn snooze() {
+        let (mut s, now) = scheduler("1h", "1h");
+        let tasks = vec![task(1, Some(at(21, 17, 0))), task(2, None)];
+        assert_eq!(check(&mut s, &now, at(21, 17, 0), &tasks), vec![1]);
+        s.snooze(1, Duration::minutes(30));
+        s.snooze(2, Duration::minutes(30));
+        assert!(check(&mut s, &now, at(21, 17, 29), &tasks).is_empty());
+        let reminders = {
+            now.set(at(21, 17, 30));
+            s.check(&tasks)
+        };
+        let uids: Vec<u64> = reminders.iter().map(|r| r.uid).collect();
+        assert_eq!(uids, vec![1, 2]);
+        assert_eq!(reminders[1].text, "Snoozed task");
+        // And then back to the nag
+        assert!(check(&mut s, &now, at(21, 17, 45), &tasks).is_empty());
+        assert_eq!(check(&mut s, &now, at(21, 18, 0), &tasks), vec![1]);
+    }
+
+    #[test]
+    fn snooze_while_overdue_holds_the_nag() {
+        let (mut s, now) = scheduler("", "1h");
+        let tasks = vec![task(1, Some(at(21, 17, 0)))];
+        assert_eq!(check(&mut s, &now, at(21, 17, 0), &tasks), vec![1]);
+        s.snooze(1, Duration::hours(2));
+        assert!(check(&mut s, &now, at(21, 18, 0), &tasks).is_empty());
+        assert_eq!(check(&mut s, &now, at(21, 19, 0), &tasks), vec![1]);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration(" 2d "), Some(Duration::days(2)));
+        assert_eq!(parse_duration("0"), Some(Duration::zero()));
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(
+            parse_leads("1d, 1h"),
+            Some(vec![Duration::days(1), Duration::hours(1)])
+        );
+        assert_eq!(parse_leads(""), Some(Vec::new()));
+        assert_eq!(parse_leads("1d,soon"), None);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+// A value in an IMAP response. Numbers, flags and NIL are atoms, and
+// quoted strings and {n} literals are both strings.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Atom(String),
+    String(Vec<u8>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn atom(&self) -> Option<&str> {
+        match *self {
+            Value::Atom(ref atom) => Some(atom),
+            _ => None,
+        }
+    }
+
+    // NIL is no string, and an atom where a string was expected is one
+    pub fn string(&self) -> Option<String> {
+        match *self {
+            Value::Atom(ref atom) if atom.eq_ignore_ascii_case("NIL") => None,
+            Value::Atom(ref atom) => Some(atom.clone()),
+            Value::String(ref bytes) => {
+                Some(String::from_utf8_lossy(bytes).into_owned())
+            }
+            Value::List(_) => None,
+        }
+    }
+
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Value::String(ref bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self) -> Option<u64> {
+        self.atom().and_then(|a| a.parse().ok())
+    }
+
+    // NIL where a list was expected is an empty one
+    pub fn list(&self) -> &[Value] {
+        match *self {
+            Value::List(ref items) => items,
+            _ => &[],
+        }
+    }
+}
+
+// The untagged responses, like "* 12 FETCH (UID 40 FLAGS (\Seen))", each
+// one a list of its values without the "*". Tagged and continuation lines
+// are skipped.
+pub fn parse(raw: &[u8]) -> Vec<Vec<Value>> {
+    let mut parser = Parser { raw: raw, pos: 0 };
+    let mut responses = Vec::new();
+    while parser.pos < raw.len() {
+        let untagged = raw[parser.pos..].starts_with(b"* ");
+        if untagged {
+            parser.pos += 2;
+        }
+        let values = parser.line();
+        if untagged {
+            responses.push(values);
+        }
+    }
+    responses
+}
+
+// The items of every FETCH response, by their upper-cased names, as in
+// "UID", "FLAGS" or "BODY[HEADER]"
+pub fn fetches(raw: &[u8]) -> Vec<HashMap<String, Value>> {
+    parse(raw)
+        .into_iter()
+        .filter(|r| {
+            r.get(1)
+                .and_then(|v| v.atom())
+                .map_or(false, |a| a.eq_ignore_ascii_case("FETCH"))
+        })
+        .filter_map(|r| r.into_iter().nth(2))
+        .map(|items| {
+            let mut fetch = HashMap::new();
+            let mut items = items.list().iter();
+            while let (Some(name), Some(value)) = (items.next(), items.next()) {
+                if let Some(name) = name.atom() {
+                    fetch.insert(name.to_uppercase(), value.clone());
+                }
+            }
+            fetch
+        })
+        .collect()
+}
+
+struct Parser<'a> {
+    raw: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.raw.get(self.pos).cloned()
+    }
+
+    // Values up to the end of the line, which literals can span
+    fn line(&mut self) -> Vec<Value> {
+        let mut values = Vec::new();
+        loop {
+            match self.peek() {
+                None => break,
+                Some(b'\n') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b' ') | Some(b'\r') | Some(b')') => self.pos += 1,
+                Some(_) => values.push(self.value()),
+            }
+        }
+        values
+    }
+
+    fn value(&mut self) -> Value {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                self.list()
+            }
+            Some(b'"') => {
+                self.pos += 1;
+                self.quoted()
+            }
+            Some(b'{') => self.literal(),
+            _ => self.atom(),
+        }
+    }
+
+    fn list(&mut self) -> Value {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(b'\r') | Some(b'\n') => break,
+                Some(b')') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b' ') => self.pos += 1,
+                Some(_) => items.push(self.value()),
+            }
+        }
+        Value::List(items)
+    }
+
+    fn quoted(&mut self) -> Value {
+        let mut bytes = Vec::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    if let Some(c) = self.peek() {
+                        bytes.push(c);
+                        self.pos += 1;
+                    }
+                }
+                c => bytes.push(c),
+            }
+        }
+        Value::String(bytes)
+    }
+
+    // {n} and a line break, then n bytes of whatever
+    fn literal(&mut self) -> Value {
+        let start = self.pos;
+        let end = match self.raw[start..].iter().position(|&c| c == b'}') {
+            Some(i) => start + i,
+            None => return self.atom(),
+        };
+        let size = String::from_utf8_lossy(&self.raw[start + 1..end])
+            .parse::<usize>();
+        let size = match size {
+            Ok(size) => size,
+            Err(_) => return self.atom(),
+        };
+        self.pos = end + 1;
+        if self.peek() == Some(b'\r') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'\n') {
+            self.pos += 1;
+        }
+        let end = (self.pos + size).min(self.raw.len());
+        let bytes = self.raw[self.pos..end].to_vec();
+        self.pos = end;
+        Value::String(bytes)
+    }
+
+    // Anything else up to a space or parenthesis, but section names like
+    // BODY[HEADER.FIELDS (FROM)] keep what's between their brackets
+    fn atom(&mut self) -> Value {
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                b'[' => depth += 1,
+                b']' if depth > 0 => depth -= 1,
+                b'\r' | b'\n' => break,
+                b' ' | b'(' | b')' if depth == 0 => break,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            // Nothing an atom could start with, so it's skipped
+            self.pos += 1;
+        }
+        let atom = String::from_utf8_lossy(&self.raw[start..self.pos]);
+        Value::Atom(atom.into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(a: &str) -> Value {
+        Value::Atom(a.to_string())
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn parse_lines() {
+        let raw = b"* 3 EXISTS\r\n* OK [UIDVALIDITY 7] Ok\r\nA1 OK Done\r\n";
+        let responses = parse(raw);
+        assert_eq!(
+            responses,
+            vec![
+                vec![atom("3"), atom("EXISTS")],
+                vec![atom("OK"), atom("[UIDVALIDITY 7]"), atom("Ok")],
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_strings() {
+        let raw = b"* LIST (\\HasNoChildren) \"/\" \"say \\\"hi\\\"\"\r\n";
+        let responses = parse(raw);
+        assert_eq!(
+            responses[0],
+            vec![
+                atom("LIST"),
+                Value::List(vec![atom("\\HasNoChildren")]),
+                string("/"),
+                string("say \"hi\""),
+            ]
+        );
+        assert_eq!(responses[0][3].string().unwrap(), "say \"hi\"");
+        assert_eq!(atom("NIL").string(), None);
+    }
+
+    #[test]
+    fn parse_literals() {
+        // Literals can hold line breaks and parentheses
+        let raw = b"* 1 FETCH (UID 5 X-GM-LABELS ({7}\r\nwo)\r\nrk \"b\") \
+                    BODY[HEADER.FIELDS (FROM)] {11}\r\nFrom: a\r\n\r\n)\r\n\
+                    * 2 FETCH (UID 6 FLAGS (\\Seen $Priority1))\r\n\
+                    A2 OK Fetch completed\r\n";
+        let fetches = fetches(raw);
+        assert_eq!(fetches.len(), 2);
+        assert_eq!(fetches[0]["UID"].number(), Some(5));
+        assert_eq!(
+            fetches[0]["X-GM-LABELS"],
+            Value::List(vec![string("wo)\r\nrk"), string("b")])
+        );
+        assert_eq!(
+            fetches[0]["BODY[HEADER.FIELDS (FROM)]"],
+            string("From: a\r\n\r\n")
+        );
+        assert_eq!(fetches[1]["UID"].number(), Some(6));
+        assert_eq!(
+            fetches[1]["FLAGS"],
+            Value::List(vec![atom("\\Seen"), atom("$Priority1")])
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use chrono::Local;
+
+use {due, launcher, Task};
+
+use std::collections::{HashMap, HashSet};
+
+// A search like `from:alice tag:work due:today report -draft`: every term has
+// to match, words without a prefix match the title, sender, tags or body, and
+// a leading "-" leaves out the tasks a term matches
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub words: Vec<String>,
+    pub from: Vec<String>,
+    pub tag: Vec<String>,
+    pub title: Vec<String>,
+    pub body: Vec<String>,
+    pub due: Vec<String>,
+    pub priority: Vec<String>,
+    // One term each
+    pub not: Vec<Query>,
+}
+
+pub fn parse(text: &str) -> Query {
+    // Quotes allow spaces, as in from:"Alice Smith"
+    let terms = launcher::split(text).unwrap_or_else(|_| {
+        text.split_whitespace().map(String::from).collect()
+    });
+
+    let mut query = Query::default();
+    for term in terms {
+        let term = term.to_lowercase();
+        if term.len() > 1 && term.starts_with('-') {
+            let mut not = Query::default();
+            add(&mut not, &term[1..]);
+            if !not.is_empty() {
+                query.not.push(not);
+            }
+        } else {
+            add(&mut query, &term);
+        }
+    }
+    query
+}
+
+fn add(query: &mut Query, term: &str) {
+    let (list, value) = match term.find(':') {
+        Some(i) => match &term[..i] {
+            "from" => (&mut query.from, &term[i + 1..]),
+            "tag" => (&mut query.tag, &term[i + 1..]),
+            "title" | "subject" => (&mut query.title, &term[i + 1..]),
+            "body" => (&mut query.body, &term[i + 1..]),
+            "due" => (&mut query.due, &term[i + 1..]),
+            "priority" => (&mut query.priority, &term[i + 1..]),
+            _ => (&mut query.words, term),
+        },
+        None => (&mut query.words, term),
+    };
+    if !value.is_empty() {
+        list.push(value.to_string());
+    }
+}
+
+impl Query {
+    pub fn is_empty(&self) -> bool {
+        *self == Query::default()
+    }
+
+    // Terms that may have to be looked for in the body of the messages
+    pub fn body_terms(&self) -> Vec<String> {
+        let not = self.not.iter().flat_map(|q| q.body_terms());
+        self.words.iter().chain(self.body.iter()).cloned().chain(not).collect()
+    }
+
+    // The body is only asked for when everything else matched, as it can be
+    // long. Bodies that aren't cached can still match through "found", the
+    // results of a search on the server.
+    pub fn matches<F>(
+        &self,
+        task: &Task,
+        body: F,
+        found: &HashMap<String, HashSet<u64>>,
+    ) -> bool
+    where
+        F: Fn(u64) -> Option<String>,
+    {
+        let now = Local::now();
+        let title = task.title.to_lowercase();
+        let sender = format!("{} {}", task.from_name, task.from_address)
+            .to_lowercase();
+        let tags: Vec<String> =
+            task.tags.iter().map(|t| t.to_lowercase()).collect();
+        let tagged = |t: &String| tags.iter().any(|k| k.contains(&t[..]));
+
+        if !self.from.iter().all(|f| sender.contains(&f[..]))
+            || !self.tag.iter().all(|t| tagged(t))
+            || !self.title.iter().all(|t| title.contains(&t[..]))
+            || !self.due.iter().all(|d| due::matches(d, task.due, now))
+            || !self.priority.iter().all(|p| task.priority().id() == p)
+        {
+            return false;
+        }
+
+        let mut text = None;
+        let mut in_body = |term: &String| {
+            if found.get(term).map_or(false, |f| f.contains(&task.uid)) {
+                return true;
+            }
+            if text.is_none() {
+                text = Some(body(task.uid).unwrap_or_default().to_lowercase());
+            }
+            text.as_ref().map_or(false, |t| t.contains(&term[..]))
+        };
+
+        let matched = self.words.iter().all(|w| {
+            title.contains(&w[..]) || sender.contains(&w[..]) || tagged(w)
+                || in_body(w)
+        }) && self.body.iter().all(|b| in_body(b));
+
+        let body: &Fn(u64) -> Option<String> = &body;
+        matched && !self.not.iter().any(|q| q.matches(task, body, found))
+    }
+}
+
+// IMAP SEARCH criteria finding a term in the body
+pub fn criteria(term: &str) -> Option<String> {
+    quote(term).map(|q| format!("BODY {}", q))
+}
+
+// Only ASCII fits in a quoted string, anything else would need a literal
+pub fn quote(text: &str) -> Option<String> {
+    if !text.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        return None;
+    }
+    let quoted = text.replace('\\', "\\\\").replace('"', "\\\"");
+    Some(format!("\"{}\"", quoted))
+}
+
+// UIDs in the untagged SEARCH responses, e.g. "* SEARCH 2 84 882"
+pub fn parse_response(response: &str) -> HashSet<u64> {
+    response
+        .lines()
+        .filter(|l| l.starts_with("* SEARCH"))
+        .flat_map(|l| l["* SEARCH".len()..].split_whitespace())
+        .filter_map(|uid| uid.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(uid: u64, title: &str, from: &str, tags: &[&str]) -> Task {
+        Task {
+            title: title.to_string(),
+            uid: uid,
+            message_id: String::new(),
+            from_name: from.to_string(),
+            from_address: format!("{}@example.com", from.to_lowercase()),
+            date: None,
+            received: None,
+            flags: Vec::new(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            in_reply_to: String::new(),
+            references: Vec::new(),
+            parent: None,
+            size: 0,
+            due: None,
+            recurrence: None,
+            priority_header: None,
+        }
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn no_body(_: u64) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn parse_terms() {
+        let query = parse(r#"Report from:"Alice Smith" subject:Q3 tag:work"#);
+        assert_eq!(query.words, strings(&["report"]));
+        assert_eq!(query.from, strings(&["alice smith"]));
+        assert_eq!(query.title, strings(&["q3"]));
+        assert_eq!(query.tag, strings(&["work"]));
+
+        let query = parse("body:invoice due:today priority:high title:a");
+        assert_eq!(query.body, strings(&["invoice"]));
+        assert_eq!(query.due, strings(&["today"]));
+        assert_eq!(query.priority, strings(&["high"]));
+        assert_eq!(query.title, strings(&["a"]));
+
+        // Unknown prefixes are just words, empty values nothing
+        let query = parse("re:lunch from: tag:");
+        assert_eq!(query.words, strings(&["re:lunch"]));
+        assert!(query.from.is_empty() && query.tag.is_empty());
+
+        // An unterminated quote still searches the words
+        let query = parse(r#"from:"alice smith"#);
+        assert_eq!(query.from, strings(&["\"alice"]));
+        assert_eq!(query.words, strings(&["smith"]));
+    }
+
+    #[test]
+    fn parse_negation() {
+        let query = parse(r#"report -draft -from:"Bob Jones" -"#);
+        assert_eq!(query.words, strings(&["report", "-"]));
+        assert_eq!(query.not.len(), 2);
+        assert_eq!(query.not[0].words, strings(&["draft"]));
+        assert_eq!(query.not[1].from, strings(&["bob jones"]));
+        assert_eq!(query.body_terms(), strings(&["report", "-", "draft"]));
+
+        assert!(parse("-from:").is_empty());
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(parse("").is_empty());
+        assert!(parse("   ").is_empty());
+        assert!(!parse("a").is_empty());
+    }
+
+    #[test]
+    fn match_fields() {
+        let found = HashMap::new();
+        let task = task(1, "Quarterly report #Work", "Alice", &["Work"]);
+        let matches = |text| parse(text).matches(&task, no_body, &found);
+
+        assert!(matches(""));
+        assert!(matches("REPORT"));
+        assert!(matches("alice"));
+        assert!(matches("example.com"));
+        assert!(matches("work"));
+        assert!(matches("from:alice tag:wo title:quarterly"));
+        assert!(matches("due:none priority:none"));
+        assert!(!matches("from:bob"));
+        assert!(!matches("report invoice"));
+        assert!(!matches("tag:home"));
+        assert!(!matches("title:alice"));
+        assert!(!matches("due:any"));
+
+        assert!(matches("-invoice -from:bob"));
+        assert!(!matches("report -quarterly"));
+        assert!(!matches("-tag:work"));
+    }
+
+    #[test]
+    fn match_body() {
+        let task = task(7, "Pay", "Alice", &[]);
+        let body = |uid| match uid {
+            7 => Some("The Invoice is attached".to_string()),
+            _ => None,
+        };
+        let mut found = HashMap::new();
+        let matches = |text, found: &HashMap<String, HashSet<u64>>| {
+            parse(text).matches(&task, &body, found)
+        };
+
+        assert!(matches("invoice", &found));
+        assert!(matches("body:attached", &found));
+        assert!(!matches("body:pay", &found));
+        assert!(!matches("-invoice", &found));
+
+        // Bodies that aren't cached match through the server's results
+        let empty = |text| parse(text).matches(&task, no_body, &found);
+        assert!(!empty("receipt"));
+        found.insert("receipt".to_string(), vec![7].into_iter().collect());
+        let empty = |text| parse(text).matches(&task, no_body, &found);
+        assert!(empty("body:receipt"));
+        assert!(!empty("-receipt"));
+    }
+}
@@ -0,0 +1,183 @@
+extern crate gdk;
+extern crate serde_json;
+
+use gtk;
+use gtk::prelude::*;
+use gtk::{Builder, Window};
+
+use mail_todo::{parser, Result};
+
+use std::collections::HashMap;
+use std::fs::File;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Next,
+    Previous,
+    Expand,
+    Collapse,
+    Toggle,
+    Complete,
+    Add,
+    Edit,
+    Due,
+    Priority,
+    Tag,
+    Refresh,
+    Search,
+    Undo,
+    Hide,
+    Shortcuts,
+}
+
+// Name in the config file, default accelerators and description of every
+// action, in the order they're listed in the shortcuts window
+const ACTIONS: [(Action, &str, &str, &str); 16] = [
+    (Action::Next, "next", "Down j", "Next task"),
+    (Action::Previous, "previous", "Up k", "Previous task"),
+    (Action::Expand, "expand", "Right l", "Show the replies to the task"),
+    (Action::Collapse, "collapse", "Left h", "Hide the replies"),
+    (Action::Toggle, "toggle", "x", "Check or uncheck the task"),
+    (Action::Complete, "complete", "Delete", "Complete the checked tasks"),
+    (Action::Add, "add", "<Control>n", "Add a task"),
+    (Action::Edit, "edit", "F2", "Edit the title of the task"),
+    (Action::Due, "due", "<Control>d", "Set the due date of the task"),
+    (
+        Action::Priority,
+        "priority",
+        "<Control>p",
+        "Raise the priority of the task, or clear it after high",
+    ),
+    (Action::Tag, "tag", "<Control>t", "Add a tag to the task"),
+    (Action::Refresh, "refresh", "F5 <Control>r", "Refresh now"),
+    (Action::Search, "search", "<Control>f", "Search"),
+    (Action::Undo, "undo", "<Control>z", "Undo"),
+    (Action::Hide, "hide", "<Control>w", "Hide the window"),
+    (
+        Action::Shortcuts,
+        "shortcuts",
+        "F1 <Control><Shift>question",
+        "Show the shortcuts",
+    ),
+];
+
+pub struct Shortcut {
+    pub action: Action,
+    pub accels: Vec<String>,
+}
+
+// The defaults, with the accelerators of the actions in the shortcuts file
+// replacing them, e.g. {"complete": ["Delete", "<Control>k"]}
+pub fn load() -> Vec<Shortcut> {
+    let custom = match read() {
+        Ok(custom) => custom,
+        Err(e) => {
+            debug!("No custom shortcuts: {}", e);
+            HashMap::new()
+        }
+    };
+    for name in custom.keys() {
+        if !ACTIONS.iter().any(|a| a.1 == *name) {
+            warn!("Unknown action '{}' in the shortcuts file", name);
+        }
+    }
+
+    ACTIONS
+        .iter()
+        .map(|&(action, name, default, _)| {
+            let accels = match custom.get(name) {
+                Some(accels) => accels.clone(),
+                None => default.split(' ').map(String::from).collect(),
+            };
+            Shortcut {
+                action: action,
+                accels: accels
+                    .into_iter()
+                    .filter(|a| {
+                        let (key, _) = gtk::accelerator_parse(a);
+                        if key == 0 {
+                            warn!("Invalid shortcut '{}' for {}", a, name);
+                        }
+                        key != 0
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+fn read() -> Result<HashMap<String, Vec<String>>> {
+    let file = File::open(parser::get_shortcuts_path()?)?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+// Action bound to the key pressed, if any. Keys without modifiers are left
+// alone while typing, so they still reach the entry.
+pub fn find(
+    shortcuts: &[Shortcut],
+    window: &Window,
+    key: u32,
+    state: gdk::ModifierType,
+) -> Option<Action> {
+    let state = state & gtk::accelerator_get_default_mod_mask();
+    let typing = window
+        .get_focus()
+        .map_or(false, |w| w.is::<gtk::Entry>() || w.is::<gtk::TextView>());
+    let key = gdk::keyval_to_lower(key);
+
+    shortcuts
+        .iter()
+        .find(|s| {
+            s.accels.iter().any(|a| {
+                let (k, mods) = gtk::accelerator_parse(a);
+                k == key && mods == state && !(typing && mods.is_empty())
+            })
+        })
+        .map(|s| s.action)
+}
+
+// A GtkShortcutsWindow listing the accelerators actually in use
+pub fn window(shortcuts: &[Shortcut], parent: &Window) -> Window {
+    let mut items = String::new();
+    for s in shortcuts.iter().filter(|s| !s.accels.is_empty()) {
+        let title = ACTIONS.iter().find(|a| a.0 == s.action).unwrap().3;
+        items.push_str(&format!(
+            r#"<child><object class="GtkShortcutsShortcut">
+                 <property name="visible">True</property>
+                 <property name="accelerator">{}</property>
+                 <property name="title">{}</property>
+               </object></child>"#,
+            escape(&s.accels.join(" ")),
+            escape(title),
+        ));
+    }
+    let ui = format!(
+        r#"<interface>
+             <object class="GtkShortcutsWindow" id="shortcuts">
+               <property name="modal">True</property>
+               <child><object class="GtkShortcutsSection">
+                 <property name="visible">True</property>
+                 <property name="section-name">shortcuts</property>
+                 <child><object class="GtkShortcutsGroup">
+                   <property name="visible">True</property>
+                   <property name="title">Tasks</property>
+                   {}
+                 </object></child>
+               </object></child>
+             </object>
+           </interface>"#,
+        items
+    );
+
+    let window: Window = Builder::new_from_string(&ui)
+        .get_object("shortcuts")
+        .unwrap();
+    window.set_transient_for(Some(parent));
+    window
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,442 @@
+use native_tls::TlsConnector;
+
+use envelope::{self, base64};
+use Result;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// Seconds to wait for the server to connect, take a command or answer it
+const TIMEOUT: u64 = 60;
+
+// A server from mutt's smtp_url, e.g. smtps://user@mail.example.com or
+// smtp://localhost:2525. Plain connections switch to TLS when the server
+// offers STARTTLS, and only log in without it to the local machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Server {
+    pub tls: bool,
+    pub user: Option<String>,
+    pub host: String,
+    pub port: u16,
+}
+
+pub fn parse_url(url: &str) -> Result<Server> {
+    let url = url.trim_matches(|c| c == '"' || c == '\'');
+    let (tls, rest) = if url.starts_with("smtps://") {
+        (true, &url["smtps://".len()..])
+    } else if url.starts_with("smtp://") {
+        (false, &url["smtp://".len()..])
+    } else {
+        return Err(format!("Not an SMTP URL: {}", url).into());
+    };
+    let rest = rest.trim_right_matches('/');
+    let (user, authority) = match rest.rfind('@') {
+        Some(i) => (Some(rest[..i].to_string()), &rest[i + 1..]),
+        None => (None, rest),
+    };
+    let (host, port) = match authority.rfind(':') {
+        Some(i) => (&authority[..i], authority[i + 1..].parse()?),
+        None => (authority, if tls { 465 } else { 25 }),
+    };
+    if host.is_empty() {
+        return Err(format!("No host in the SMTP URL: {}", url).into());
+    }
+    Ok(Server {
+        tls: tls,
+        user: user,
+        host: host.to_string(),
+        port: port,
+    })
+}
+
+// Sends a whole message, headers included, logging in first if the server
+// has a user
+pub fn send(
+    server: &Server,
+    pass: &str,
+    from: &str,
+    to: &str,
+    message: &[u8],
+) -> Result<()> {
+    info!("Sending mail to {} through {}", to, server.host);
+    let stream = connect(&server.host, server.port)?;
+    stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+
+    if server.tls {
+        let stream = TlsConnector::new()?.connect(&server.host, stream)?;
+        let mut conn = BufReader::new(stream);
+        let features = greet(&mut conn)?;
+        let message = Mail::new(message, &features)?;
+        return transaction(&mut conn, server, pass, from, to, &message);
+    }
+
+    let mut conn = BufReader::new(stream);
+    let features = greet(&mut conn)?;
+    if features.iter().any(|f| f.eq_ignore_ascii_case("STARTTLS")) {
+        command(&mut conn, "STARTTLS", 220)?;
+        let stream = conn.into_inner();
+        let stream = TlsConnector::new()?.connect(&server.host, stream)?;
+        let mut conn = BufReader::new(stream);
+        let features = command(&mut conn, "EHLO mail-todo", 250)?;
+        let message = Mail::new(message, &features)?;
+        return transaction(&mut conn, server, pass, from, to, &message);
+    }
+
+    // Without STARTTLS, maybe because someone in between took it out of
+    // the reply, the password would go in the clear
+    let local = conn.get_ref().peer_addr()?.ip().is_loopback();
+    if server.user.is_some() && !local {
+        let _ = command(&mut conn, "QUIT", 221);
+        return Err(format!(
+            "{} doesn't offer STARTTLS, not sending the password unencrypted",
+            server.host
+        ).into());
+    }
+    let message = Mail::new(message, &features)?;
+    transaction(&mut conn, server, pass, from, to, &message)
+}
+
+// Tries every address the host has, giving each TIMEOUT seconds, so an
+// unreachable server doesn't hold up the poller for long
+fn connect(host: &str, port: u16) -> Result<TcpStream> {
+    let mut last = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, Duration::from_secs(TIMEOUT)) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = Some(e),
+        }
+    }
+    match last {
+        Some(e) => Err(e.into()),
+        None => Err(format!("No address for {}", host).into()),
+    }
+}
+
+// A message as it can go to the server: as it is if it's all ASCII or the
+// server takes 8 bits, which MAIL FROM then says, or else with its body
+// in quoted-printable
+struct Mail {
+    data: Vec<u8>,
+    eight_bit: bool,
+}
+
+impl Mail {
+    fn new(message: &[u8], features: &[String]) -> Result<Mail> {
+        if message.is_ascii() {
+            return Ok(Mail {
+                data: message.to_vec(),
+                eight_bit: false,
+            });
+        }
+        if features.iter().any(|f| f.eq_ignore_ascii_case("8BITMIME")) {
+            return Ok(Mail {
+                data: message.to_vec(),
+                eight_bit: true,
+            });
+        }
+
+        let end = match message.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(i) => i + 4,
+            None => match message.windows(2).position(|w| w == b"\n\n") {
+                Some(i) => i + 2,
+                None => message.len(),
+            },
+        };
+        let (headers, body) = message.split_at(end);
+        let encoding = "quoted-printable";
+        let mut data = envelope::set_header(
+            headers,
+            "Content-Transfer-Encoding",
+            encoding,
+        )?;
+        data.extend_from_slice(quoted_printable(body).as_bytes());
+        Ok(Mail {
+            data: data,
+            eight_bit: false,
+        })
+    }
+}
+
+// Every byte that isn't printable ASCII as =XX, and lines no longer than
+// 76 characters with the soft line breaks
+fn quoted_printable(text: &[u8]) -> String {
+    let mut out = String::new();
+    let mut lines: Vec<&[u8]> = text.split(|&c| c == b'\n').collect();
+    if lines.last().map_or(false, |l| l.is_empty()) {
+        lines.pop();
+    }
+    for line in lines {
+        let line = if line.ends_with(b"\r") {
+            &line[..line.len() - 1]
+        } else {
+            line
+        };
+        let mut width = 0;
+        for (i, &c) in line.iter().enumerate() {
+            // Spaces at the end of a line could get lost on the way
+            let last = i + 1 == line.len();
+            let encoded = match c {
+                b' ' | b'\t' if !last => (c as char).to_string(),
+                c if c > b' ' && c <= b'~' && c != b'=' => {
+                    (c as char).to_string()
+                }
+                c => format!("={:02X}", c),
+            };
+            if width + encoded.len() > 75 {
+                out.push_str("=\r\n");
+                width = 0;
+            }
+            width += encoded.len();
+            out.push_str(&encoded);
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// The greeting and the extensions the server supports
+fn greet<S: Read + Write>(conn: &mut BufReader<S>) -> Result<Vec<String>> {
+    reply(conn, "the greeting", 220)?;
+    command(conn, "EHLO mail-todo", 250)
+}
+
+fn transaction<S: Read + Write>(
+    conn: &mut BufReader<S>,
+    server: &Server,
+    pass: &str,
+    from: &str,
+    to: &str,
+    message: &Mail,
+) -> Result<()> {
+    if let Some(ref user) = server.user {
+        let plain = base64(format!("\0{}\0{}", user, pass).as_bytes());
+        command(conn, &format!("AUTH PLAIN {}", plain), 235)
+            .map_err(|_| "The SMTP server didn't accept the password")?;
+    }
+    let body = if message.eight_bit { " BODY=8BITMIME" } else { "" };
+    command(conn, &format!("MAIL FROM:<{}>{}", from, body), 250)?;
+    command(conn, &format!("RCPT TO:<{}>", to), 250)?;
+    command(conn, "DATA", 354)?;
+
+    // Lines starting with a dot get another one, so they aren't taken for
+    // the end of the message
+    let text = String::from_utf8_lossy(&message.data);
+    let mut data = String::new();
+    for line in text.lines() {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push_str(".\r\n");
+    conn.get_mut().write_all(data.as_bytes())?;
+    reply(conn, "the message", 250)?;
+    let _ = command(conn, "QUIT", 221);
+    Ok(())
+}
+
+// Sends a command and reads its reply, which has to have that code
+fn command<S: Read + Write>(
+    conn: &mut BufReader<S>,
+    line: &str,
+    code: u16,
+) -> Result<Vec<String>> {
+    let name = line.split_whitespace().next().unwrap_or("").to_string();
+    debug!("SMTP > {}", if name == "AUTH" { "AUTH ..." } else { line });
+    conn.get_mut().write_all(format!("{}\r\n", line).as_bytes())?;
+    reply(conn, &name, code)
+}
+
+// The text of every line of a reply like "250-first\r\n250 last\r\n"
+fn reply<S: Read>(
+    conn: &mut BufReader<S>,
+    to: &str,
+    code: u16,
+) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if conn.read_line(&mut line)? == 0 {
+            return Err("The SMTP server closed the connection".into());
+        }
+        let line = line.trim_right();
+        debug!("SMTP < {}", line);
+        if line.get(..3).and_then(|c| c.parse::<u16>().ok()) != Some(code) {
+            return Err(format!("SMTP server answered '{}' to {}", line, to)
+                .into());
+        }
+        lines.push(line.get(4..).unwrap_or("").to_string());
+        if !line.get(3..).map_or(false, |l| l.starts_with('-')) {
+            return Ok(lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    const EHLO: &'static [u8] =
+        b"250-sink\r\n250-8BITMIME\r\n250 AUTH PLAIN\r\n";
+
+    fn sink(reject: &'static str) -> (Server, thread::JoinHandle<Vec<String>>) {
+        sink_with(reject, EHLO)
+    }
+
+    // A local SMTP sink taking one message, answering 550 to the command
+    // starting with "reject" and "ehlo" to EHLO, and returning every line
+    // it got
+    fn sink_with(
+        reject: &'static str,
+        ehlo: &'static [u8],
+    ) -> (Server, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server {
+            tls: false,
+            user: None,
+            host: String::from("127.0.0.1"),
+            port: listener.local_addr().unwrap().port(),
+        };
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut conn = BufReader::new(stream);
+            conn.get_mut().write_all(b"220 sink ESMTP\r\n").unwrap();
+            let mut lines = Vec::new();
+            let mut data = false;
+            loop {
+                let mut line = String::new();
+                if conn.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_right_matches("\r\n").to_string();
+                lines.push(line.clone());
+                let reply: &[u8] = if data {
+                    if line != "." {
+                        continue;
+                    }
+                    data = false;
+                    b"250 Queued\r\n"
+                } else if !reject.is_empty() && line.starts_with(reject) {
+                    b"550 No\r\n"
+                } else if line.starts_with("EHLO") {
+                    ehlo
+                } else if line.starts_with("AUTH") {
+                    b"235 Welcome\r\n"
+                } else if line == "DATA" {
+                    data = true;
+                    b"354 Go on\r\n"
+                } else if line == "QUIT" {
+                    conn.get_mut().write_all(b"221 Bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 OK\r\n"
+                };
+                conn.get_mut().write_all(reply).unwrap();
+            }
+            lines
+        });
+        (server, handle)
+    }
+
+    #[test]
+    fn send_to_a_sink() {
+        let (server, sink) = sink("");
+        let message = b"Subject: Digest\r\n\r\nfirst\r\n.hidden\r\n..two\r\n";
+        send(&server, "", "me@example.com", "you@example.org", message)
+            .unwrap();
+        assert_eq!(
+            sink.join().unwrap(),
+            vec![
+                "EHLO mail-todo",
+                "MAIL FROM:<me@example.com>",
+                "RCPT TO:<you@example.org>",
+                "DATA",
+                "Subject: Digest",
+                "",
+                "first",
+                "..hidden",
+                "...two",
+                ".",
+                "QUIT",
+            ]
+        );
+    }
+
+    #[test]
+    fn send_8bit() {
+        let message = "Subject: Digest\r\n\r\n- Caf\u{e9}\r\n".as_bytes();
+        let (server, sink) = sink("");
+        send(&server, "", "me@example.com", "me@example.com", message)
+            .unwrap();
+        let lines = sink.join().unwrap();
+        assert_eq!(lines[1], "MAIL FROM:<me@example.com> BODY=8BITMIME");
+        assert_eq!(lines[6], "- Caf\u{e9}");
+
+        // Without 8BITMIME, the body goes in quoted-printable
+        let (server, sink) = sink_with("", b"250 sink\r\n");
+        send(&server, "", "me@example.com", "me@example.com", message)
+            .unwrap();
+        let lines = sink.join().unwrap();
+        assert_eq!(lines[1], "MAIL FROM:<me@example.com>");
+        assert_eq!(
+            &lines[4..8],
+            &[
+                "Subject: Digest",
+                "Content-Transfer-Encoding: quoted-printable",
+                "",
+                "- Caf=C3=A9",
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_quoted_printable() {
+        assert_eq!(quoted_printable(b"a = b \r\n"), "a =3D b=20\r\n");
+        let long = [b'x'; 80];
+        assert_eq!(
+            quoted_printable(&long),
+            format!("{}=\r\n{}\r\n", "x".repeat(75), "x".repeat(5))
+        );
+    }
+
+    #[test]
+    fn log_in_locally_without_starttls() {
+        let (mut server, sink) = sink("");
+        server.user = Some(String::from("me"));
+        send(&server, "secret", "me@example.com", "me@example.com", b"Hi")
+            .unwrap();
+        let lines = sink.join().unwrap();
+        assert_eq!(lines[1], "AUTH PLAIN AG1lAHNlY3JldA==");
+    }
+
+    #[test]
+    fn send_fails_on_a_rejection() {
+        let (server, sink) = sink("RCPT");
+        assert!(send(&server, "", "me@example.com", "x@y", b"Hi").is_err());
+        assert_eq!(sink.join().unwrap().last().unwrap(), "RCPT TO:<x@y>");
+    }
+
+    #[test]
+    fn parse_urls() {
+        assert_eq!(
+            parse_url("smtps://me@mail.example.com/").unwrap(),
+            Server {
+                tls: true,
+                user: Some(String::from("me")),
+                host: String::from("mail.example.com"),
+                port: 465,
+            }
+        );
+        let server = parse_url("'smtp://localhost:2525'").unwrap();
+        assert_eq!((server.tls, server.port), (false, 2525));
+        assert_eq!(server.user, None);
+        assert!(parse_url("imap://mail.example.com").is_err());
+        assert!(parse_url("smtp://me@").is_err());
+    }
+}
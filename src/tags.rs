@@ -0,0 +1,246 @@
+use regex::{self, Regex};
+
+use response::Value;
+
+// Tags come from the IMAP keywords of the message, its Gmail labels and
+// the #hashtags in its subject, in that order and without repeating any
+pub fn collect(
+    keywords: &[&str],
+    labels: &[String],
+    title: &str,
+) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let all = keywords
+        .iter()
+        .map(|k| k.to_string())
+        .chain(labels.iter().cloned())
+        .chain(hashtags(title));
+    for tag in all {
+        if !tags.iter().any(|t| t.to_lowercase() == tag.to_lowercase()) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+// "Call the bank #finance #urgent" has "finance" and "urgent". Only numbers
+// don't count, as in "Fix #123".
+pub fn hashtags(title: &str) -> Vec<String> {
+    let re = Regex::new(r"(?:^|\s)#([\w-]+)").unwrap();
+    re.captures_iter(title)
+        .map(|c| c[1].to_string())
+        .filter(|t| !t.chars().all(|c| c.is_ascii_digit()))
+        .collect()
+}
+
+// The title with a hashtag appended, for servers that don't take keywords
+pub fn add_hashtag(title: &str, tag: &str) -> String {
+    format!("{} #{}", title.trim_right(), tag)
+}
+
+pub fn remove_hashtag(title: &str, tag: &str) -> String {
+    let re = Regex::new(&format!(r"(?i)(^|\s)#{}(\s|$)", regex::escape(tag)))
+        .unwrap();
+    // Until it's gone, as the space between two of them only counts once
+    let mut title = title.to_string();
+    loop {
+        let removed = re.replace_all(&title, " ").into_owned();
+        if removed == title {
+            break;
+        }
+        title = removed;
+    }
+    title.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// The labels in an X-GM-LABELS list, e.g. (\Inbox "Big project" Work),
+// leaving out Gmail's own ones like \Inbox or \Important. Like folder
+// names, they come in modified UTF-7.
+pub fn labels(list: &[Value]) -> Vec<String> {
+    list.iter()
+        .filter_map(|l| l.string())
+        .filter(|l| !l.is_empty() && !l.starts_with('\\'))
+        .map(|l| decode_utf7(&l).unwrap_or(l))
+        .collect()
+}
+
+// Base64 with a comma for the slash and no padding
+const UTF7_CHARS: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+,";
+
+// Modified UTF-7 from RFC 3501, as in "Caf&AOk-" for "Café": printable
+// ASCII stands for itself, but for "&" that's "&-", and anything else is
+// UTF-16 in base64 between "&" and "-"
+pub fn encode_utf7(text: &str) -> String {
+    let mut out = String::new();
+    let mut wide: Vec<u16> = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c < ' ' || c > '~' {
+            let mut units = [0; 2];
+            wide.extend_from_slice(c.encode_utf16(&mut units));
+            let ascii = chars.peek().map_or(true, |&c| c >= ' ' && c <= '~');
+            if !ascii {
+                continue;
+            }
+            let bytes: Vec<u8> = wide
+                .drain(..)
+                .flat_map(|u| vec![(u >> 8) as u8, u as u8])
+                .collect();
+            out.push('&');
+            for chunk in bytes.chunks(3) {
+                let n = chunk
+                    .iter()
+                    .chain(&[0, 0])
+                    .take(3)
+                    .fold(0u32, |n, &b| n << 8 | b as u32);
+                for i in 0..chunk.len() + 1 {
+                    let index = (n >> (18 - 6 * i)) & 63;
+                    out.push(UTF7_CHARS[index as usize] as char);
+                }
+            }
+            out.push('-');
+        } else if c == '&' {
+            out.push_str("&-");
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// None when it isn't modified UTF-7
+pub fn decode_utf7(text: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let end = start + rest[start..].find('-')?;
+        let encoded = &rest[start + 1..end];
+        if encoded.is_empty() {
+            out.push('&');
+        } else {
+            let mut bits = 0u32;
+            let mut count = 0;
+            let mut bytes = Vec::new();
+            for c in encoded.bytes() {
+                let value = UTF7_CHARS.iter().position(|&u| u == c)?;
+                bits = bits << 6 | value as u32;
+                count += 6;
+                if count >= 8 {
+                    count -= 8;
+                    bytes.push((bits >> count) as u8);
+                }
+            }
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .filter(|pair| pair.len() == 2)
+                .map(|pair| (pair[0] as u16) << 8 | pair[1] as u16)
+                .collect();
+            out.push_str(&String::from_utf16(&units).ok()?);
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+// Keywords mail clients keep for themselves rather than as tags, besides
+// the ones starting with $, like Thunderbird's Junk and NonJunk
+const CLIENT_KEYWORDS: [&'static str; 5] =
+    ["junk", "nonjunk", "notjunk", "forwarded", "redirected"];
+
+pub fn is_client_keyword(flag: &str) -> bool {
+    flag.starts_with('$') || CLIENT_KEYWORDS.contains(&&flag.to_lowercase()[..])
+}
+
+// Whether it can be an IMAP keyword, which is an atom that can't start
+// with a backslash, and one that would show as a tag
+pub fn is_keyword(tag: &str) -> bool {
+    !tag.is_empty()
+        && !tag.starts_with('\\')
+        && !is_client_keyword(tag)
+        && tag.chars().all(|c| {
+            c.is_ascii() && !c.is_ascii_control() && !" (){%*\"\\]".contains(c)
+        })
+}
+
+// Whether it can be a hashtag in the subject
+pub fn is_hashtag(tag: &str) -> bool {
+    hashtags(&format!("#{}", tag)) == vec![tag.to_string()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_hashtags() {
+        let tags = hashtags("#bank Call the bank #finance #to-do_2 #urgent");
+        assert_eq!(tags, vec!["bank", "finance", "to-do_2", "urgent"]);
+        assert_eq!(hashtags("Fix #123 and issue#4"), Vec::<String>::new());
+        assert_eq!(hashtags("Café #réunion"), vec!["réunion"]);
+        assert!(is_hashtag("work"));
+        assert!(!is_hashtag("two words"));
+        assert!(!is_hashtag("42"));
+    }
+
+    #[test]
+    fn edit_hashtags() {
+        let title = add_hashtag("Call the bank ", "finance");
+        assert_eq!(title, "Call the bank #finance");
+        let title = "#Work Call #work the bank #work #working";
+        assert_eq!(remove_hashtag(title, "work"), "Call the bank #working");
+        assert_eq!(remove_hashtag("Pay #a.b now", "a.b"), "Pay now");
+        assert_eq!(remove_hashtag("Pay #axb now", "a.b"), "Pay #axb now");
+    }
+
+    #[test]
+    fn collect_tags() {
+        let labels = vec![String::from("Work"), String::from("Home")];
+        let tags = collect(&["work", "urgent"], &labels, "Fix it #home #new");
+        assert_eq!(tags, vec!["work", "urgent", "Home", "new"]);
+    }
+
+    #[test]
+    fn keywords() {
+        assert!(is_keyword("work"));
+        assert!(is_keyword("to-do"));
+        assert!(!is_keyword(""));
+        assert!(!is_keyword("\\Flagged"));
+        assert!(!is_keyword("two words"));
+        assert!(!is_keyword("a(b"));
+        assert!(!is_keyword("café"));
+        assert!(!is_keyword("NonJunk"));
+        assert!(!is_keyword("$Forwarded"));
+        assert!(is_client_keyword("$label1"));
+        assert!(is_client_keyword("junk"));
+        assert!(!is_client_keyword("junkyard"));
+    }
+
+    #[test]
+    fn utf7() {
+        assert_eq!(encode_utf7("Work"), "Work");
+        assert_eq!(encode_utf7("Café"), "Caf&AOk-");
+        assert_eq!(encode_utf7("R&D"), "R&-D");
+        assert_eq!(encode_utf7("台北/日本語"), "&U,BTFw-/&ZeVnLIqe-");
+        assert_eq!(decode_utf7("Caf&AOk-").unwrap(), "Café");
+        assert_eq!(decode_utf7("R&-D").unwrap(), "R&D");
+        let decoded = decode_utf7("&U,BTFw-/&ZeVnLIqe-").unwrap();
+        assert_eq!(decoded, "台北/日本語");
+        assert_eq!(decode_utf7("A&B"), None);
+        for label in &["Big project", "Café crème", "déjà & vu", "🎉"] {
+            assert_eq!(decode_utf7(&encode_utf7(label)).unwrap(), *label);
+        }
+    }
+
+    #[test]
+    fn parse_labels() {
+        let list = vec![
+            Value::Atom("\\Inbox".to_string()),
+            Value::String(b"Big project".to_vec()),
+            Value::Atom("Caf&AOk-".to_string()),
+        ];
+        assert_eq!(labels(&list), vec!["Big project", "Café"]);
+    }
+}
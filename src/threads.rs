@@ -0,0 +1,190 @@
+use Task;
+
+use std::collections::HashMap;
+
+// Links replies to the task that started their thread. The parent of a
+// message is the nearest one in its In-Reply-To or References that's in
+// the folder, and its root the one at the top of those. Threads are kept
+// flat, with every reply under the root, and loops make no thread at all.
+pub fn link(tasks: &mut [Task]) {
+    let by_id: HashMap<&str, usize> = tasks
+        .iter()
+        .enumerate()
+        .filter(|&(_, t)| !t.message_id.is_empty())
+        .map(|(i, t)| (&t.message_id[..], i))
+        .collect();
+    let parents: Vec<Option<usize>> = tasks
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            Some(&t.in_reply_to)
+                .into_iter()
+                .chain(t.references.iter().rev())
+                .filter_map(|id| by_id.get(&id[..]).cloned())
+                .find(|&p| p != i)
+        })
+        .collect();
+
+    let roots: Vec<Option<u64>> = (0..tasks.len())
+        .map(|i| {
+            let mut seen = vec![i];
+            let mut current = i;
+            while let Some(parent) = parents[current] {
+                if seen.contains(&parent) {
+                    return None;
+                }
+                seen.push(parent);
+                current = parent;
+            }
+            if current == i {
+                None
+            } else {
+                Some(tasks[current].uid)
+            }
+        })
+        .collect();
+    for (task, root) in tasks.iter_mut().zip(roots) {
+        task.parent = root;
+    }
+}
+
+// The task that started the thread, which is the task itself if it's not
+// a reply
+pub fn root<'a>(tasks: &'a HashMap<u64, Task>, task: &'a Task) -> &'a Task {
+    task.parent.and_then(|uid| tasks.get(&uid)).unwrap_or(task)
+}
+
+// A task and, if it starts a thread, its replies, which are completed along
+// with it
+pub fn with_replies<'a, I>(tasks: I, uid: u64) -> Vec<u64>
+where
+    I: IntoIterator<Item = &'a Task>,
+{
+    let mut uids = vec![uid];
+    uids.extend(
+        tasks
+            .into_iter()
+            .filter(|t| t.parent == Some(uid))
+            .map(|t| t.uid),
+    );
+    uids
+}
+
+// How many replies each thread has, by the UID of its root
+pub fn replies<'a, I>(tasks: I) -> HashMap<u64, usize>
+where
+    I: IntoIterator<Item = &'a Task>,
+{
+    let mut replies = HashMap::new();
+    for uid in tasks.into_iter().filter_map(|t| t.parent) {
+        *replies.entry(uid).or_insert(0) += 1;
+    }
+    replies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(uid: u64, id: &str, reply_to: &str, references: &[&str]) -> Task {
+        Task {
+            title: format!("Task {}", uid),
+            uid: uid,
+            message_id: id.to_string(),
+            from_name: String::new(),
+            from_address: String::new(),
+            date: None,
+            received: None,
+            flags: Vec::new(),
+            tags: Vec::new(),
+            in_reply_to: reply_to.to_string(),
+            references: references.iter().map(|r| r.to_string()).collect(),
+            parent: None,
+            size: 0,
+            due: None,
+            recurrence: None,
+            priority_header: None,
+        }
+    }
+
+    fn parents(tasks: &[Task]) -> Vec<Option<u64>> {
+        tasks.iter().map(|t| t.parent).collect()
+    }
+
+    #[test]
+    fn link_replies() {
+        let mut tasks = vec![
+            task(1, "a@x", "", &[]),
+            task(2, "b@x", "a@x", &["a@x"]),
+            // The parent is in the folder, its root at the top
+            task(3, "c@x", "b@x", &["a@x", "b@x"]),
+            task(4, "", "a@x", &[]),
+        ];
+        link(&mut tasks);
+        assert_eq!(parents(&tasks), vec![None, Some(1), Some(1), Some(1)]);
+    }
+
+    #[test]
+    fn link_precedence() {
+        // In-Reply-To goes first, then the last of the References
+        let mut tasks = vec![
+            task(1, "a@x", "", &[]),
+            task(2, "b@x", "", &[]),
+            task(3, "c@x", "b@x", &["a@x"]),
+            task(4, "d@x", "", &["a@x", "b@x"]),
+            task(5, "e@x", "gone@x", &["a@x", "gone@y"]),
+        ];
+        link(&mut tasks);
+        assert_eq!(
+            parents(&tasks),
+            vec![None, None, Some(2), Some(2), Some(1)]
+        );
+    }
+
+    #[test]
+    fn link_orphans() {
+        let mut tasks = vec![
+            task(1, "a@x", "gone@x", &["older@x", "gone@x"]),
+            task(2, "b@x", "", &[]),
+            // Replying to itself is no thread
+            task(3, "c@x", "c@x", &["c@x"]),
+        ];
+        link(&mut tasks);
+        assert_eq!(parents(&tasks), vec![None, None, None]);
+    }
+
+    #[test]
+    fn link_cycle() {
+        let mut tasks = vec![
+            task(1, "a@x", "b@x", &[]),
+            task(2, "b@x", "", &["a@x"]),
+            task(3, "c@x", "a@x", &[]),
+        ];
+        link(&mut tasks);
+        assert_eq!(parents(&tasks), vec![None, None, None]);
+    }
+
+    #[test]
+    fn thread_replies() {
+        let mut tasks = vec![
+            task(1, "a@x", "", &[]),
+            task(2, "b@x", "a@x", &[]),
+            task(3, "c@x", "b@x", &[]),
+            task(4, "d@x", "", &[]),
+        ];
+        link(&mut tasks);
+
+        assert_eq!(with_replies(&tasks, 1), vec![1, 2, 3]);
+        assert_eq!(with_replies(&tasks, 2), vec![2]);
+        assert_eq!(with_replies(&tasks, 4), vec![4]);
+
+        let counts = replies(&tasks);
+        assert_eq!(counts.get(&1), Some(&2));
+        assert_eq!(counts.get(&4), None);
+
+        let by_uid: HashMap<u64, Task> =
+            tasks.into_iter().map(|t| (t.uid, t)).collect();
+        assert_eq!(root(&by_uid, &by_uid[&3]).uid, 1);
+        assert_eq!(root(&by_uid, &by_uid[&4]).uid, 4);
+    }
+}
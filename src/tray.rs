@@ -0,0 +1,263 @@
+extern crate ksni;
+
+use self::ksni::menu::{CheckmarkItem, MenuItem, StandardItem};
+use self::ksni::{Handle, ToolTip, TrayService};
+
+use gtk::prelude::*;
+use gtk::{CheckMenuItem, Menu, MenuItem as GtkMenuItem, SeparatorMenuItem,
+          StatusIcon};
+
+use mail_todo::{self, control, Message, Task};
+
+use std::collections::HashSet;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+// How many titles to show in the tooltip and in the menu
+const TOOLTIP_TASKS: usize = 5;
+const MENU_TASKS: usize = 15;
+
+// StatusNotifierItem is what current panels (KDE, GNOME with the
+// AppIndicator extension, waybar, ...) show. GtkStatusIcon is only kept for
+// the old XEmbed trays, when there's no StatusNotifierWatcher around.
+pub enum Tray {
+    Sni(Handle<Sni>),
+    Legacy(Legacy),
+}
+
+pub struct Sni {
+    ui: Sender<Message>,
+    poller: Sender<Message>,
+    tasks: Vec<Task>,
+}
+
+pub struct Legacy {
+    icon: StatusIcon,
+    menu: Menu,
+    ui: Sender<Message>,
+    poller: Sender<Message>,
+}
+
+impl Tray {
+    pub fn new(ui: Sender<Message>, poller: Sender<Message>) -> Tray {
+        let fallback = ui.clone();
+        let service = TrayService::new(Sni {
+            ui: ui,
+            poller: poller,
+            tasks: Vec::new(),
+        });
+        let handle = service.handle();
+        let _ = thread::Builder::new()
+            .name("tray".to_string())
+            .spawn(move || {
+                if let Err(e) = service.run() {
+                    warn!("StatusNotifierItem failed: {}", e);
+                    let _ = fallback.send(Message::TrayFallback);
+                }
+            });
+        Tray::Sni(handle)
+    }
+
+    pub fn fallback(&mut self, ui: Sender<Message>, poller: Sender<Message>) {
+        let tasks = match *self {
+            Tray::Legacy(_) => return,
+            Tray::Sni(ref handle) => {
+                handle.shutdown();
+                handle.update(|t| t.tasks.clone())
+            }
+        };
+        info!("Falling back to GtkStatusIcon");
+
+        let icon = StatusIcon::new_from_icon_name(mail_todo::ICON);
+        let toggle = ui.clone();
+        icon.connect_activate(move |_| {
+            let _ = toggle.send(Message::Toggle);
+        });
+
+        let menu = Menu::new();
+        let popup = menu.clone();
+        icon.connect_popup_menu(move |_, button, time| {
+            popup.popup_easy(button, time);
+        });
+
+        let legacy = Legacy {
+            icon: icon,
+            menu: menu,
+            ui: ui,
+            poller: poller,
+        };
+        legacy.update(&tasks);
+        *self = Tray::Legacy(legacy);
+    }
+
+    // Threads are listed by their root, and completing it completes them
+    pub fn set_tasks(&self, tasks: &HashSet<Task>) {
+        let mut tasks = control::sorted(tasks);
+        tasks.retain(|t| t.parent.is_none());
+        match *self {
+            Tray::Sni(ref handle) => handle.update(|t| t.tasks = tasks),
+            Tray::Legacy(ref legacy) => legacy.update(&tasks),
+        }
+    }
+}
+
+impl Legacy {
+    fn update(&self, tasks: &[Task]) {
+        self.icon.set_tooltip_text(Some(&tooltip(tasks)[..]));
+
+        for child in self.menu.get_children() {
+            self.menu.remove(&child);
+        }
+        for task in tasks.iter().take(MENU_TASKS) {
+            let item = CheckMenuItem::new_with_label(task.title.trim());
+            let tx = self.ui.clone();
+            let uid = task.uid;
+            item.connect_toggled(move |_| {
+                let _ = tx.send(Message::Complete(uid));
+            });
+            self.menu.append(&item);
+        }
+        if tasks.len() > MENU_TASKS {
+            let more = format!("{} more...", tasks.len() - MENU_TASKS);
+            self.menu.append(&menu_item(&more, &self.ui, Message::Present));
+        }
+        if !tasks.is_empty() {
+            self.menu.append(&SeparatorMenuItem::new());
+        }
+
+        let ui = &self.ui;
+        let poller = &self.poller;
+        self.menu.append(&menu_item("Show/hide", ui, Message::Toggle));
+        self.menu.append(&menu_item("Refresh now", poller, Message::Refresh));
+        self.menu.append(&menu_item("Add task...", ui, Message::AskTask));
+        self.menu.append(&SeparatorMenuItem::new());
+        self.menu.append(&menu_item("Quit", ui, Message::Quit));
+        self.menu.show_all();
+    }
+}
+
+fn menu_item(label: &str, tx: &Sender<Message>, msg: Message) -> GtkMenuItem {
+    let item = GtkMenuItem::new_with_label(label);
+    let tx = tx.clone();
+    item.connect_activate(move |_| {
+        let _ = tx.send(msg.clone());
+    });
+    item
+}
+
+fn pending_text(pending: usize) -> String {
+    match pending {
+        0 => String::from("Nothing pending"),
+        1 => String::from("1 task pending"),
+        n => format!("{} tasks pending", n),
+    }
+}
+
+fn tooltip(tasks: &[Task]) -> String {
+    let mut text = pending_text(tasks.len());
+    for task in tasks.iter().take(TOOLTIP_TASKS) {
+        text.push_str("\n• ");
+        text.push_str(task.title.trim());
+    }
+    if tasks.len() > TOOLTIP_TASKS {
+        text.push_str("\n…");
+    }
+    text
+}
+
+impl ksni::Tray for Sni {
+    fn id(&self) -> String {
+        String::from("mail-todo")
+    }
+
+    fn title(&self) -> String {
+        format!("{} ({})", mail_todo::NAME, self.tasks.len())
+    }
+
+    fn icon_name(&self) -> String {
+        String::from(mail_todo::ICON)
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        ToolTip {
+            title: String::from(mail_todo::NAME),
+            description: tooltip(&self.tasks),
+            icon_name: String::from(mail_todo::ICON),
+            icon_pixmap: Vec::new(),
+        }
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        let _ = self.ui.send(Message::Toggle);
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let mut menu: Vec<MenuItem<Self>> = Vec::new();
+        for task in self.tasks.iter().take(MENU_TASKS) {
+            let uid = task.uid;
+            menu.push(
+                CheckmarkItem {
+                    label: task.title.trim().replace("_", "__"),
+                    activate: Box::new(move |t: &mut Sni| {
+                        let _ = t.ui.send(Message::Complete(uid));
+                    }),
+                    ..Default::default()
+                }.into(),
+            );
+        }
+        if self.tasks.len() > MENU_TASKS {
+            let more = self.tasks.len() - MENU_TASKS;
+            menu.push(
+                StandardItem {
+                    label: format!("{} more...", more),
+                    activate: Box::new(|t: &mut Sni| {
+                        let _ = t.ui.send(Message::Present);
+                    }),
+                    ..Default::default()
+                }.into(),
+            );
+        }
+        if !self.tasks.is_empty() {
+            menu.push(MenuItem::Separator);
+        }
+
+        menu.extend(vec![
+            StandardItem {
+                label: String::from("Show/hide"),
+                activate: Box::new(|t: &mut Sni| {
+                    let _ = t.ui.send(Message::Toggle);
+                }),
+                ..Default::default()
+            }.into(),
+            StandardItem {
+                label: String::from("Refresh now"),
+                activate: Box::new(|t: &mut Sni| {
+                    let _ = t.poller.send(Message::Refresh);
+                }),
+                ..Default::default()
+            }.into(),
+            StandardItem {
+                label: String::from("Add task..."),
+                activate: Box::new(|t: &mut Sni| {
+                    let _ = t.ui.send(Message::AskTask);
+                }),
+                ..Default::default()
+            }.into(),
+            MenuItem::Separator,
+            StandardItem {
+                label: String::from("Quit"),
+                icon_name: String::from("application-exit"),
+                activate: Box::new(|t: &mut Sni| {
+                    let _ = t.ui.send(Message::Quit);
+                }),
+                ..Default::default()
+            }.into(),
+        ]);
+        menu
+    }
+
+    fn watcher_offine(&self) -> bool {
+        let _ = self.ui.send(Message::TrayFallback);
+        false
+    }
+}
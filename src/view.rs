@@ -0,0 +1,413 @@
+use chrono::Local;
+use serde_json;
+
+use {parser, threads, Result, Task};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File};
+
+// How the task list is ordered and grouped, kept between runs
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct View {
+    pub sort: Sort,
+    pub group: Group,
+    // Keys of the tasks in the order they were arranged by hand
+    pub manual: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    Received,
+    Title,
+    Sender,
+    Due,
+    Priority,
+    Manual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Group {
+    None,
+    Sender,
+    Day,
+    Tag,
+}
+
+impl Default for Sort {
+    fn default() -> Sort {
+        Sort::Received
+    }
+}
+
+impl Default for Group {
+    fn default() -> Group {
+        Group::None
+    }
+}
+
+// The ids are the ones of the sort and group combo boxes in the UI
+impl Sort {
+    pub fn id(&self) -> &'static str {
+        match *self {
+            Sort::Received => "received",
+            Sort::Title => "title",
+            Sort::Sender => "sender",
+            Sort::Due => "due",
+            Sort::Priority => "priority",
+            Sort::Manual => "manual",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Sort> {
+        match id {
+            "received" => Some(Sort::Received),
+            "title" => Some(Sort::Title),
+            "sender" => Some(Sort::Sender),
+            "due" => Some(Sort::Due),
+            "priority" => Some(Sort::Priority),
+            "manual" => Some(Sort::Manual),
+            _ => None,
+        }
+    }
+}
+
+impl Group {
+    pub fn id(&self) -> &'static str {
+        match *self {
+            Group::None => "none",
+            Group::Sender => "sender",
+            Group::Day => "day",
+            Group::Tag => "tag",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Group> {
+        match id {
+            "none" => Some(Group::None),
+            "sender" => Some(Group::Sender),
+            "day" => Some(Group::Day),
+            "tag" => Some(Group::Tag),
+            _ => None,
+        }
+    }
+}
+
+pub fn load() -> View {
+    parser::get_view_path()
+        .ok()
+        .and_then(|path| File::open(path).ok())
+        .and_then(|file| serde_json::from_reader(file).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(view: &View) -> Result<()> {
+    let path = parser::get_view_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    serde_json::to_writer(File::create(path)?, view)?;
+    Ok(())
+}
+
+// What identifies a task in the manual order. Message ids survive the
+// folder being rebuilt on the server, UIDs don't.
+pub fn manual_key(task: &Task) -> String {
+    if task.message_id.is_empty() {
+        format!("uid:{}", task.uid)
+    } else {
+        task.message_id.clone()
+    }
+}
+
+// Groups go first so they stay together, then the sort key, and the UID
+// as a last resort so the order never depends on the row order
+pub fn compare(view: &View, a: &Task, b: &Task) -> Ordering {
+    compare_groups(view.group, a, b)
+        .then_with(|| compare_tasks(view, a, b))
+        .then_with(|| a.uid.cmp(&b.uid))
+}
+
+// Replies go right under the task that started their thread, oldest
+// first, wherever that one goes
+pub fn compare_threads(
+    view: &View,
+    tasks: &HashMap<u64, Task>,
+    a: &Task,
+    b: &Task,
+) -> Ordering {
+    let (root_a, root_b) = (threads::root(tasks, a), threads::root(tasks, b));
+    if root_a.uid != root_b.uid {
+        return compare(view, root_a, root_b);
+    }
+    a.parent
+        .is_some()
+        .cmp(&b.parent.is_some())
+        .then_with(|| a.date.cmp(&b.date))
+        .then_with(|| a.uid.cmp(&b.uid))
+}
+
+fn compare_groups(group: Group, a: &Task, b: &Task) -> Ordering {
+    match group {
+        Group::None => Ordering::Equal,
+        // Most recent day first, like a mail client
+        Group::Day => day(b).cmp(&day(a)),
+        // Tasks without a sender or tag after all the others
+        Group::Sender | Group::Tag => {
+            let key = |t: &Task| match group {
+                Group::Sender if !t.sender().is_empty() => {
+                    Some(t.sender().to_lowercase())
+                }
+                Group::Tag => tag(t).map(|t| t.to_lowercase()),
+                _ => None,
+            };
+            match (key(a), key(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        }
+    }
+}
+
+fn compare_tasks(view: &View, a: &Task, b: &Task) -> Ordering {
+    let received = |a: &Task, b: &Task| a.date.cmp(&b.date);
+    match view.sort {
+        Sort::Received => received(a, b),
+        Sort::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+        Sort::Sender => a.sender()
+            .to_lowercase()
+            .cmp(&b.sender().to_lowercase())
+            .then_with(|| received(a, b)),
+        // Tasks without a due date after all the others
+        Sort::Due => match (a.due, b.due) {
+            (Some(x), Some(y)) => x.cmp(&y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }.then_with(|| received(a, b)),
+        Sort::Priority => b.priority()
+            .cmp(&a.priority())
+            .then_with(|| received(a, b)),
+        // Tasks never arranged by hand go after the ones that were
+        Sort::Manual => {
+            let position = |t: &Task| {
+                let key = manual_key(t);
+                view.manual
+                    .iter()
+                    .position(|k| *k == key)
+                    .unwrap_or(view.manual.len())
+            };
+            position(a)
+                .cmp(&position(b))
+                .then_with(|| received(a, b))
+        }
+    }
+}
+
+fn day(task: &Task) -> Option<String> {
+    task.date
+        .map(|d| d.with_timezone(&Local).format("%Y-%m-%d").to_string())
+}
+
+fn tag(task: &Task) -> Option<String> {
+    task.tags.first().cloned()
+}
+
+// Header above a task, if the one shown before it is in another group.
+// Replies are in the group of their thread.
+pub fn header_above(
+    group: Group,
+    tasks: &HashMap<u64, Task>,
+    task: &Task,
+    before: Option<&Task>,
+) -> Option<String> {
+    let text = header(group, threads::root(tasks, task))?;
+    let same = before.map_or(false, |b| {
+        header(group, threads::root(tasks, b)).as_ref() == Some(&text)
+    });
+    if same {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+// Text of the header above the first task of each group
+pub fn header(group: Group, task: &Task) -> Option<String> {
+    match group {
+        Group::None => None,
+        Group::Sender if task.sender().is_empty() => {
+            Some(String::from("Unknown sender"))
+        }
+        Group::Sender => Some(task.sender().to_string()),
+        Group::Day => Some(
+            task.date
+                .map(|d| {
+                    d.with_timezone(&Local).format("%A, %e %B %Y").to_string()
+                })
+                .unwrap_or_else(|| String::from("No date")),
+        ),
+        Group::Tag => {
+            Some(tag(task).unwrap_or_else(|| String::from("Untagged")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{DateTime, FixedOffset, TimeZone};
+
+    fn at(day: u32, hour: u32) -> Option<DateTime<FixedOffset>> {
+        let date = Local.ymd(2026, 10, day).and_hms(hour, 0, 0);
+        Some(date.with_timezone(date.offset()))
+    }
+
+    fn task(uid: u64, title: &str, from: &str, day: u32) -> Task {
+        Task {
+            title: title.to_string(),
+            uid: uid,
+            message_id: format!("{}@example.com", uid),
+            from_name: from.to_string(),
+            from_address: String::new(),
+            date: at(day, 12),
+            received: None,
+            flags: Vec::new(),
+            tags: Vec::new(),
+            in_reply_to: String::new(),
+            references: Vec::new(),
+            parent: None,
+            size: 0,
+            due: None,
+            recurrence: None,
+            priority_header: None,
+        }
+    }
+
+    fn sorted(view: &View, tasks: &[Task]) -> Vec<u64> {
+        let mut tasks: Vec<&Task> = tasks.iter().collect();
+        tasks.sort_by(|a, b| compare(view, a, b));
+        tasks.iter().map(|t| t.uid).collect()
+    }
+
+    fn view(sort: Sort, group: Group) -> View {
+        View {
+            sort: sort,
+            group: group,
+            manual: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sort_received() {
+        let mut undated = task(4, "d", "", 1);
+        undated.date = None;
+        let tasks = [task(3, "c", "", 20), undated, task(1, "a", "", 10)];
+        let view = view(Sort::Received, Group::None);
+        assert_eq!(sorted(&view, &tasks), vec![4, 1, 3]);
+    }
+
+    #[test]
+    fn sort_due() {
+        let mut tasks = vec![
+            task(1, "a", "", 10),
+            task(2, "b", "", 11),
+            task(3, "c", "", 12),
+            task(4, "d", "", 13),
+        ];
+        tasks[1].due = at(25, 9);
+        tasks[3].due = at(22, 9);
+
+        // Undated ones last, by when they came in
+        let view = view(Sort::Due, Group::None);
+        assert_eq!(sorted(&view, &tasks), vec![4, 2, 1, 3]);
+
+        // The same due date goes by when they came in
+        tasks[0].due = at(22, 9);
+        assert_eq!(sorted(&view, &tasks), vec![1, 4, 2, 3]);
+    }
+
+    #[test]
+    fn sort_others() {
+        let mut tasks = vec![
+            task(1, "banana", "Carol", 10),
+            task(2, "Apple", "alice", 11),
+            task(3, "cherry", "", 12),
+        ];
+        tasks[2].from_address = String::from("bob@example.com");
+        tasks[2].flags = vec![String::from("\\Flagged")];
+
+        let title = view(Sort::Title, Group::None);
+        assert_eq!(sorted(&title, &tasks), vec![2, 1, 3]);
+        let sender = view(Sort::Sender, Group::None);
+        assert_eq!(sorted(&sender, &tasks), vec![2, 3, 1]);
+        let priority = view(Sort::Priority, Group::None);
+        assert_eq!(sorted(&priority, &tasks)[0], 3);
+
+        // Tasks never arranged by hand go last
+        let mut manual = view(Sort::Manual, Group::None);
+        manual.manual = vec![manual_key(&tasks[2]), manual_key(&tasks[0])];
+        assert_eq!(sorted(&manual, &tasks), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn group_order() {
+        let mut tasks = vec![
+            task(1, "a", "Bob", 10),
+            task(2, "b", "", 11),
+            task(3, "c", "alice", 12),
+            task(4, "d", "Bob", 13),
+        ];
+        let by_sender = view(Sort::Title, Group::Sender);
+        assert_eq!(sorted(&by_sender, &tasks), vec![3, 1, 4, 2]);
+
+        // Most recent day first, undated last
+        tasks[1].date = None;
+        tasks[3].date = at(10, 8);
+        let by_day = view(Sort::Title, Group::Day);
+        assert_eq!(sorted(&by_day, &tasks), vec![3, 1, 4, 2]);
+
+        tasks[0].tags = vec![String::from("work")];
+        tasks[2].tags = vec![String::from("Home"), String::from("work")];
+        let by_tag = view(Sort::Received, Group::Tag);
+        assert_eq!(sorted(&by_tag, &tasks), vec![3, 1, 2, 4]);
+    }
+
+    #[test]
+    fn group_headers() {
+        let mut tasks = HashMap::new();
+        for task in vec![
+            task(1, "a", "Bob", 10),
+            task(2, "b", "Bob", 11),
+            task(3, "c", "", 12),
+            task(4, "Re: c", "Bob", 13),
+        ] {
+            tasks.insert(task.uid, task);
+        }
+        tasks.get_mut(&4).unwrap().parent = Some(3);
+        let above = |group, uid, before: Option<u64>| {
+            let before = before.map(|b| &tasks[&b]);
+            header_above(group, &tasks, &tasks[&uid], before)
+        };
+        let bob = Some(String::from("Bob"));
+        let unknown = Some(String::from("Unknown sender"));
+
+        assert_eq!(above(Group::Sender, 1, None), bob);
+        assert_eq!(above(Group::Sender, 2, Some(1)), None);
+        assert_eq!(above(Group::Sender, 3, Some(2)), unknown);
+        // A reply stays in the group of its thread
+        assert_eq!(above(Group::Sender, 4, Some(3)), None);
+        assert_eq!(above(Group::Sender, 4, Some(2)), unknown);
+        assert_eq!(above(Group::None, 1, None), None);
+
+        let day = above(Group::Day, 2, Some(1)).unwrap();
+        assert!(day.contains("October 2026"), day);
+        assert_eq!(above(Group::Day, 2, Some(2)), None);
+        assert_eq!(above(Group::Tag, 1, None), Some(String::from("Untagged")));
+    }
+}